@@ -20,74 +20,70 @@
 //! OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 //! THE SOFTWARE.
 //!
-//! A simple program that aggregates the proofs of multiple programs proven with the zkVM.
+//! A program that aggregates the proofs of a VNNS search into a single, fixed-size result, either
+//! at once or as a node of an aggregation tree whose nodes are proven by this program as well.
 //!
-//! https://github.com/succinctlabs/sp1/tree/main/examples/aggregation
+//! Based on https://github.com/succinctlabs/sp1/tree/main/examples/aggregation
 //!
-//! cargo prove build --elf-name riscv32im-succinct-aggregator-elf   
+//! cargo prove build --elf-name riscv32im-succinct-aggregator-elf
 
 #![no_main]
 sp1_zkvm::entrypoint!(main);
 
 use sha2::{Digest, Sha256};
-
-pub fn words_to_bytes_le(words: &[u32; 8]) -> [u8; 32] {
-    let mut bytes = [0u8; 32];
-    for i in 0..8 {
-        let word_bytes = words[i].to_le_bytes();
-        bytes[i * 4..(i + 1) * 4].copy_from_slice(&word_bytes);
-    }
-    bytes
-}
-
-/// Encode a list of vkeys and committed values into a single byte array. In the future this could
-/// be a merkle tree or some other commitment scheme.
-///
-/// ( vkeys.len() || vkeys || committed_values[0].len as u32 || committed_values[0] || ... )
-pub fn commit_proof_pairs(vkeys: &[[u32; 8]], committed_values: &[Vec<u8>]) -> Vec<u8> {
-    assert_eq!(vkeys.len(), committed_values.len());
-    let mut res = Vec::with_capacity(
-        4 + vkeys.len() * 32
-            + committed_values.len() * 4
-            + committed_values
-                .iter()
-                .map(|vals| vals.len())
-                .sum::<usize>(),
-    );
-
-    // Note we use big endian because abi.encodePacked in solidity does also
-    res.extend_from_slice(&(vkeys.len() as u32).to_be_bytes());
-    for vkey in vkeys.iter() {
-        res.extend_from_slice(&words_to_bytes_le(vkey));
-    }
-    for vals in committed_values.iter() {
-        res.extend_from_slice(&(vals.len() as u32).to_be_bytes());
-        res.extend_from_slice(vals);
-    }
-
-    res
-}
+use vnns_lib::{aggregate_root, decode_nodes, merge_nodes, proof_nodes, AggregatedOutputs};
 
 pub fn main() {
-    // Read the verification keys.
-    let vkeys = sp1_zkvm::io::read::<Vec<[u32; 8]>>();
+    // Read the verifying key of the VNNS program, which every proof must be verified against, and
+    // the key of this program, which every aggregator below this one must be verified against.
+    // Neither is pinned, both are committed, so a verifier must compare them to the expected keys,
+    // see `AggregatedOutputs`.
+    let vkey = sp1_zkvm::io::read::<[u32; 8]>();
+    let aggregator_vkey = sp1_zkvm::io::read::<[u32; 8]>();
 
-    // Read the public values.
+    // Read the height of this node within the aggregation tree, or `None` for its root.
+    let height = sp1_zkvm::io::read::<Option<u32>>();
+
+    // Read the number of the first proof if the proofs are VNNS proofs, or `None` if they are
+    // nodes of the aggregation tree, and their public values in the order they were generated.
+    let start = sp1_zkvm::io::read::<Option<usize>>();
     let public_values = sp1_zkvm::io::read::<Vec<Vec<u8>>>();
 
-    // Verify the proofs.
-    assert_eq!(vkeys.len(), public_values.len());
-    for i in 0..vkeys.len() {
-        let vkey = &vkeys[i];
-        let public_values = &public_values[i];
+    // Below the root, the final proof is only known by its digest, so its public values are read
+    // as well at the root of a tree.
+    let final_public_values = match (height, start) {
+        (Some(_), _) => Vec::new(),
+        (None, Some(_)) => public_values.last().cloned().unwrap_or_default(),
+        (None, None) => sp1_zkvm::io::read::<Vec<u8>>(),
+    };
+
+    // Verify the proofs, where the nodes must have been committed by this program itself.
+    let proofs_vkey = if start.is_some() {
+        vkey
+    } else {
+        aggregator_vkey
+    };
+    for public_values in &public_values {
         let public_values_digest = Sha256::digest(public_values);
-        sp1_zkvm::lib::verify::verify_sp1_proof(vkey, &public_values_digest.into());
+        sp1_zkvm::lib::verify::verify_sp1_proof(&proofs_vkey, &public_values_digest.into());
     }
+    let nodes = match start {
+        Some(start) => proof_nodes(vkey, aggregator_vkey, start, &public_values),
+        None => decode_nodes(vkey, aggregator_vkey, &public_values),
+    };
+
+    let result = nodes.and_then(|nodes| match height {
+        // The node of a consecutive run of proofs, which an aggregator above this one merges.
+        Some(height) => merge_nodes(&nodes, height).map(|node| node.encode()),
+        // The proofs must be the rounds of a single search, ending with a final proof that covers
+        // the whole dataset, whose result is committed along with the Merkle root over all proofs
+        // instead of all public values.
+        None => aggregate_root(&nodes, &final_public_values).map(|aggregated| aggregated.encode()),
+    });
 
-    // TODO: Do something interesting with the proofs here.
-    //
-    // For example, commit to the verified proofs in a merkle tree. For now, we'll just commit to
-    // all the (vkey, input) pairs.
-    let commitment = commit_proof_pairs(&vkeys, &public_values);
-    sp1_zkvm::io::commit_slice(&commitment);
+    // an invalid input is committed as a rejected aggregation, instead of aborting, which an
+    // aggregator above this one rejects in turn
+    let bytes = result
+        .unwrap_or_else(|err| AggregatedOutputs::rejected(vkey, aggregator_vkey, err).encode());
+    sp1_zkvm::io::commit_slice(&bytes);
 }
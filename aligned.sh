@@ -24,17 +24,31 @@ balance() {
     --user_addr "$address"
 }
 
+# Reads the manifest of the proving run, whose file paths are relative to its directory
+manifest() {
+  local json_path=$1
+  local manifest_path="${json_path%.json}.manifest.json"
+
+  if [ ! -f "$manifest_path" ]; then
+    echo "Manifest not found: $manifest_path" >&2
+    exit 1
+  fi
+  echo "$manifest_path"
+}
+
 # Submit the final aggregated proof only
 submitagg() {
   local keystore_path=$1
-  local json_path=$2
-  local proof_path="${json_path%.json}.agg.proof"
-  local pub_path="${json_path%.json}.agg.pub"
+  local manifest_path
+  manifest_path=$(manifest "$2") || exit 1
+  local dir=$(dirname "$manifest_path")
 
-  if [ ! -f "$proof_path" ] || [ ! -f "$pub_path" ]; then
-    echo "Required files not found: $proof_path or $pub_path"
+  if [ "$(jq -r '.aggregation' "$manifest_path")" = "null" ]; then
+    echo "The run has not been aggregated: $manifest_path"
     exit 1
   fi
+  local proof_path="$dir/$(jq -r '.aggregation.proof_path' "$manifest_path")"
+  local pub_path="$dir/$(jq -r '.aggregation.public_values_path' "$manifest_path")"
 
   # submit using aggregator elf
   aligned submit \
@@ -48,22 +62,19 @@ submitagg() {
     --rpc_url $RPC_URL
 }
 
-# Submit all proofs that resulted from the batched proofs
+# Submit all proofs that resulted from the batched proofs, as listed by the manifest
 submit() {
   local keystore_path=$1
-  local json_path=$2
-
-  local index=0
-  while true; do
-    local proof_path="${json_path%.json}.$index.proof"
-    local pub_path="${json_path%.json}.$index.pub"
+  local manifest_path
+  manifest_path=$(manifest "$2") || exit 1
+  local dir=$(dirname "$manifest_path")
 
-    if [ ! -f "$proof_path" ] || [ ! -f "$pub_path" ]; then
-      echo "Submitted $index proofs in total."
-      break
-    fi
+  jq -r '.proofs[] | "\(.number) \(.round) \(.chunk) \(.proof_path) \(.public_values_path)"' \
+    "$manifest_path" | while read -r number round chunk proof_file pub_file; do
+    local proof_path="$dir/$proof_file"
+    local pub_path="$dir/$pub_file"
 
-    echo "Submitting proof $index"
+    echo "Submitting proof $number (round $round chunk $chunk)"
     # submit using VNNS elf
     aligned submit \
       --proving_system SP1 \
@@ -73,10 +84,9 @@ submit() {
       --batcher_url wss://batcher.alignedlayer.com \
       --keystore_path "$keystore_path" \
       --network $NETWORK \
-      --rpc_url $RPC_URL
-
-    index=$((index + 1))
+      --rpc_url $RPC_URL < /dev/null
   done
+  echo "Submitted $(jq '.proofs | length' "$manifest_path") proofs in total."
 }
 
 ### Main ###
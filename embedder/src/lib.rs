@@ -1,8 +1,17 @@
 use ollama_rs::{generation::embeddings::request::GenerateEmbeddingsRequest, Ollama};
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
 use std::path::Path;
 use tokio::fs;
+use vnns_lib::encoding::{item_hash, payload_hash};
+use vnns_lib::{
+    EmbeddingMatrix, Hash, MerkleProof, MerkleTree, Metadata, PublicOutputs, Quantization,
+};
+
+pub const DEFAULT_MODEL: &str = "all-minilm:latest"; // dim: 384
+
+// pub const DEFAULT_MODEL: &str = "nomic-embed-text:latest"; // dim: 768
+
+// pub const DEFAULT_MODEL: &str = "mxbai-embed-large:latest"; // dim: 1024
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Data {
@@ -10,16 +19,142 @@ pub struct Data {
     description: String,
 }
 
+/// An item within the data file, with its optional metadata next to the raw data.
+#[derive(Clone, Debug, Deserialize)]
+struct DataRecord {
+    #[serde(flatten)]
+    data: Data,
+    #[serde(default)]
+    metadata: Metadata,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct EmbeddedData<T> {
     /// Raw data object.
     pub data: T,
+    /// Filterable attributes, which are not embedded.
+    #[serde(default, skip_serializing_if = "Metadata::is_empty")]
+    pub metadata: Metadata,
     /// Embedding vector.
     pub embeddings: Vec<f32>,
-    /// Hex encoded SHA256 digest.
+    /// Hex encoded SHA256 digest of the embedding, the metadata & the raw data, see [`item_hash`] &
+    /// [`data_hash`].
     pub hash: String,
 }
 
+/// Quantized embeddings of an index, in the same order as the embedded data.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QuantizedIndex {
+    /// Quantization parameters of the dataset.
+    pub quantization: Quantization,
+    /// Quantized embedding vectors.
+    pub embeddings: EmbeddingMatrix<i16>,
+    /// Hex encoded Merkle root over the hashes of the quantized samples, which include the
+    /// quantization parameters.
+    pub root: String,
+}
+
+/// Inclusion proof of an item within the dataset, as printed by [`inclusion`], or of a proof within
+/// an aggregation.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Inclusion {
+    /// Hex encoded Merkle root of the dataset, or of the aggregated proofs.
+    pub root: String,
+    /// Hex encoded hash of the item or the proof, i.e. the leaf.
+    pub hash: String,
+    pub proof: MerkleProof,
+}
+
+/// Opening of a result within a private dataset, which lets the querier check the item behind an
+/// output commitment without the rest of the dataset.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ResultReceipt<T> {
+    /// Index of the item within the dataset.
+    pub idx: usize,
+    /// The item itself.
+    pub item: EmbeddedData<T>,
+    /// Quantized embedding of the item, which is the committed one if the search is quantized.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quantized_embeddings: Option<Vec<i16>>,
+    /// Inclusion proof of the item within the dataset.
+    pub inclusion: Inclusion,
+}
+
+impl<T: Serialize> ResultReceipt<T> {
+    /// Hash of the item in the representation that was searched, i.e. its output commitment, given
+    /// the quantization parameters of the search if it is quantized.
+    pub fn item_hash(&self, quantization: Option<&Quantization>) -> Hash {
+        let payload = data_hash(&self.item.data);
+        let metadata = &self.item.metadata;
+        match &self.quantized_embeddings {
+            Some(embeddings) => item_hash(embeddings, quantization, metadata, &payload),
+            None => item_hash(&self.item.embeddings, None, metadata, &payload),
+        }
+    }
+
+    /// Whether the item is a result of the proof with the given public values.
+    pub fn verify(&self, outputs: &PublicOutputs) -> bool {
+        let item_hash = self.item_hash(outputs.quantization.as_ref());
+        outputs.is_result(self.idx, &item_hash, &self.inclusion.proof)
+    }
+}
+
+/// Collects the embeddings of the given data into a matrix.
+pub fn embedding_matrix<T>(data: &[EmbeddedData<T>]) -> EmbeddingMatrix {
+    EmbeddingMatrix::from_rows(
+        &data
+            .iter()
+            .map(|data| data.embeddings.as_slice())
+            .collect::<Vec<_>>(),
+    )
+    .expect("embeddings must have the same dimension")
+}
+
+/// Payload hash of the raw data object of an item, i.e. the hash of its JSON encoding.
+pub fn data_hash<T: Serialize>(data: &T) -> Hash {
+    payload_hash(&serde_json::to_vec(data).expect("data must be serializable"))
+}
+
+/// Builds the Merkle tree over the `hash` of each item.
+pub fn dataset_tree<T>(data: &[EmbeddedData<T>]) -> MerkleTree {
+    MerkleTree::new(data.iter().map(|data| decode_hash(&data.hash)).collect())
+}
+
+/// Builds the Merkle tree over the hashes of the quantized samples, with the quantization
+/// parameters, the metadata & the raw data of each item.
+pub fn quantized_tree<T: Serialize>(
+    quantization: &Quantization,
+    embeddings: &EmbeddingMatrix<i16>,
+    data: &[EmbeddedData<T>],
+) -> MerkleTree {
+    assert_eq!(
+        embeddings.len(),
+        data.len(),
+        "expected an embedding per item"
+    );
+    MerkleTree::new(
+        embeddings
+            .rows()
+            .zip(data)
+            .map(|(row, data)| {
+                item_hash(
+                    row,
+                    Some(quantization),
+                    &data.metadata,
+                    &data_hash(&data.data),
+                )
+            })
+            .collect(),
+    )
+}
+
+fn decode_hash(hash: &str) -> Hash {
+    hex::decode(hash)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .expect("invalid hash")
+}
+
 impl std::fmt::Display for Data {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}: {}", self.name, self.description)
@@ -33,11 +168,14 @@ pub async fn index(path: &str, model: &str) {
     println!("Reading data from: {}", path);
     let path = Path::new(path);
     let data_bytes = fs::read(path).await.expect("Unable to read file");
-    let data = serde_json::from_slice::<Vec<Data>>(&data_bytes).unwrap();
+    let data = serde_json::from_slice::<Vec<DataRecord>>(&data_bytes).unwrap();
     assert!(!data.is_empty(), "no data found");
 
     // convert to texts
-    let texts = data.iter().map(|d| d.to_string()).collect::<Vec<String>>();
+    let texts = data
+        .iter()
+        .map(|record| record.data.to_string())
+        .collect::<Vec<String>>();
 
     // generate embeddings
     println!("Generating embeddings with: {}", model);
@@ -45,26 +183,30 @@ pub async fn index(path: &str, model: &str) {
     let res = ollama.generate_embeddings(request).await.unwrap();
 
     // convert to embedded data
-    println!(
-        "Exporting embedding data (dim: {})",
-        res.embeddings[0].len()
-    );
+    let embeddings = EmbeddingMatrix::from_rows(&res.embeddings)
+        .expect("embeddings must have the same dimension");
+    println!("Exporting embedding data (dim: {})", embeddings.dim());
     let embedded_data = data
         .into_iter()
-        .zip(res.embeddings)
-        .map(|(data, embeddings)| {
-            let embeddings_bytes = embeddings
-                .iter()
-                .flat_map(|f| f.to_ne_bytes())
-                .collect::<Vec<_>>();
-
-            EmbeddedData {
-                data,
-                embeddings,
-                hash: hex::encode(Sha256::digest(&embeddings_bytes)),
-            }
+        .zip(embeddings.rows())
+        .map(|(record, row)| EmbeddedData {
+            hash: hex::encode(item_hash(
+                row,
+                None,
+                &record.metadata,
+                &data_hash(&record.data),
+            )),
+            data: record.data,
+            embeddings: row.to_vec(),
+            metadata: record.metadata,
         })
         .collect::<Vec<EmbeddedData<Data>>>();
+    // the root commits to the size of the dataset, which the proofs commit to as well
+    println!(
+        "Dataset root: {}",
+        hex::encode(dataset_tree(&embedded_data).root())
+    );
+    println!("Dataset size: {}", embedded_data.len());
 
     // write embedded data to file
     let output_path = path.with_extension("index.json");
@@ -92,3 +234,67 @@ pub async fn query(path: &str, text: &str, model: &str) {
         .await
         .expect("Unable to write file");
 }
+
+pub async fn quantize(path: &str, bits: u8) {
+    // read embedded data
+    let input_path = Path::new(path).with_extension("index.json");
+    println!("Reading index from: {:?}", input_path);
+    let index_bytes = fs::read(&input_path).await.expect("Unable to read file");
+    let embedded_data = serde_json::from_slice::<Vec<EmbeddedData<Data>>>(&index_bytes).unwrap();
+    let embeddings = embedding_matrix(&embedded_data);
+
+    // quantize with a scale fitted to the entire dataset
+    let quantization = Quantization::fit(&embeddings, bits);
+    println!(
+        "Quantizing embeddings (bits: {}, scale: {})",
+        quantization.bits, quantization.scale
+    );
+    let embeddings = quantization.quantize_matrix(&embeddings);
+    let root = quantized_tree(&quantization, &embeddings, &embedded_data).root();
+    println!("Dataset root: {}", hex::encode(root));
+    println!("Dataset size: {}", embedded_data.len());
+    let quantized_index = QuantizedIndex {
+        quantization,
+        embeddings,
+        root: hex::encode(root),
+    };
+
+    // write quantized index to file
+    let output_path = Path::new(path).with_extension("qindex.json");
+    println!("Writing data to: {:?}", output_path);
+    let quantized_index_bytes = serde_json::to_vec(&quantized_index).unwrap();
+    fs::write(output_path, quantized_index_bytes)
+        .await
+        .expect("Unable to write file");
+}
+
+/// Prints the inclusion proof of the item at the given index, within the index or within the
+/// quantized index.
+pub async fn inclusion(path: &str, idx: usize, quantized: bool) {
+    let index_bytes = fs::read(Path::new(path).with_extension("index.json"))
+        .await
+        .expect("Unable to read file");
+    let embedded_data = serde_json::from_slice::<Vec<EmbeddedData<Data>>>(&index_bytes).unwrap();
+    assert!(idx < embedded_data.len(), "index out of bounds");
+
+    let tree = if quantized {
+        let quantized_bytes = fs::read(Path::new(path).with_extension("qindex.json"))
+            .await
+            .expect("Unable to read file");
+        let quantized_index = serde_json::from_slice::<QuantizedIndex>(&quantized_bytes).unwrap();
+        quantized_tree(
+            &quantized_index.quantization,
+            &quantized_index.embeddings,
+            &embedded_data,
+        )
+    } else {
+        dataset_tree(&embedded_data)
+    };
+
+    let inclusion = Inclusion {
+        root: hex::encode(tree.root()),
+        hash: hex::encode(tree.leaf(idx)),
+        proof: tree.proof(idx),
+    };
+    println!("{}", serde_json::to_string_pretty(&inclusion).unwrap());
+}
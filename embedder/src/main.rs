@@ -9,12 +9,6 @@ struct Cli {
     command: Commands,
 }
 
-const DEFAULT_MODEL: &str = "all-minilm:latest"; // dim: 384
-
-// const DEFAULT_MODEL: &str = "nomic-embed-text:latest"; // dim: 768
-
-// const DEFAULT_MODEL: &str = "mxbai-embed-large:latest"; // dim: 1024
-
 #[derive(Subcommand)]
 enum Commands {
     /// Index data at given path and generate embeddings
@@ -33,6 +27,22 @@ enum Commands {
         #[arg(short, long, help = "Model to use for embedding generation", default_value = DEFAULT_MODEL)]
         model: String,
     },
+    /// Quantize the embeddings of an existing index to integers
+    Quantize {
+        #[arg(short, long, help = "Path to the data file")]
+        path: String,
+        #[arg(short, long, help = "Bit-width, 8 or 16", default_value = "8")]
+        bits: u8,
+    },
+    /// Print the Merkle inclusion proof of an item within the dataset
+    Inclusion {
+        #[arg(short, long, help = "Path to the data file")]
+        path: String,
+        #[arg(short, long, help = "Index of the item within the dataset")]
+        index: usize,
+        #[arg(short, long, help = "Prove inclusion within the quantized index")]
+        quantized: bool,
+    },
 }
 
 #[tokio::main]
@@ -46,5 +56,15 @@ async fn main() {
         Commands::Query { path, text, model } => {
             query(path, text, model).await;
         }
+        Commands::Quantize { path, bits } => {
+            quantize(path, *bits).await;
+        }
+        Commands::Inclusion {
+            path,
+            index,
+            quantized,
+        } => {
+            inclusion(path, *index, *quantized).await;
+        }
     }
 }
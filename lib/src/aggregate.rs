@@ -0,0 +1,766 @@
+//! Aggregation of the proofs of a search into a single, fixed-size result.
+//!
+//! The aggregated proofs are committed as the root of a Merkle tree over their
+//! [`proof_hash`]es, so that any of them can be shown to be part of the aggregation with its
+//! inclusion proof alone, see [`proofs_tree`].
+//!
+//! Many proofs can be aggregated by a tree of aggregators instead of at once, where each
+//! aggregator verifies up to a fan-out of proofs, or of the aggregators below it, and commits an
+//! [`AggregationNode`]. The node of a contiguous run of proofs is the subtree of [`proofs_tree`]
+//! above them, along with the state of the rounds they cover, so that the root of the aggregation
+//! tree commits the same [`AggregatedOutputs`] regardless of its shape.
+
+use sha2::{Digest, Sha256};
+use std::ops::Range;
+
+use crate::encoding::{
+    decode_optional_score, encode_index, encode_optional_score, proof_hash, root_hash, tagged_hash,
+    Domain, Reader,
+};
+use crate::{
+    empty_root, subtree_root, DecodeError, Hash, MerkleProof, MerkleTree, PublicOutputs, Score,
+    VnnsError, STATUS_OK,
+};
+
+/// Public values committed by the aggregation program, which are the same size for any search.
+///
+/// The aggregation program pins neither verifying key, it commits the ones it was given as input,
+/// so a verifier must check both committed keys against the expected programs, otherwise the proof
+/// may aggregate the proofs of any program.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AggregatedOutputs {
+    /// Status code, see [`crate::VnnsError::code`]. A rejected aggregation commits the verifying
+    /// keys it was given, with everything else zero.
+    pub status: u8,
+    /// Verifying key digest of the VNNS program, which every aggregated proof is verified against.
+    pub program_vkey: [u32; 8],
+    /// Verifying key digest that the aggregators below the root are verified against, which a
+    /// verifier checks to be the key of the aggregation program itself.
+    pub aggregator_vkey: [u32; 8],
+    /// Merkle root of the dataset, which commits to its size, see [`crate::MerkleTree`].
+    pub dataset_root: Hash,
+    /// Number of samples within the dataset, as committed by every proof, all of which are
+    /// covered by the final proof.
+    pub dataset_size: usize,
+    /// Hash of the query, salted or not, see [`crate::encoding::query_commitment`].
+    pub query_commitment: Hash,
+    /// Number of results of the final proof.
+    pub num_results: usize,
+    /// Dataset index, output commitment & score of the first result, or `None` if there are no
+    /// results.
+    pub winner: Option<(usize, Hash, Score)>,
+    /// Margin of the final proof, see [`PublicOutputs::margin`].
+    pub margin: Option<Score>,
+    /// SHA-256 digest of the public values of the final proof, which commit to the metric, the
+    /// mode, the filter & all results.
+    pub final_digest: Hash,
+    /// Root of the Merkle tree over the aggregated proofs, in the order they were generated.
+    pub proofs_root: Hash,
+}
+
+impl AggregatedOutputs {
+    /// Version of the layout, to be incremented whenever it changes.
+    pub const VERSION: u8 = 5;
+
+    /// Size of the encoded public values.
+    pub const SIZE: usize = 1 + 1 + 32 + 32 + 32 + 4 + 32 + 4 + 4 + 32 + 8 + 9 + 32 + 32;
+
+    /// Byte representation of the public values, to be committed by the aggregation program.
+    ///
+    /// ( version || status || program_vkey as u32s || aggregator_vkey as u32s || dataset_root ||
+    /// dataset_size as u32 || query_commitment || num_results as u32 || winner idx as u32 ||
+    /// winner output_commitment || winner score as i64 || margin as optional score ||
+    /// final_digest || proofs_root ), where the winner is zero if there is none.
+    pub fn encode(&self) -> Vec<u8> {
+        let (winner_idx, winner_commitment, winner_score) = self.winner.unwrap_or_default();
+
+        let mut bytes = Vec::with_capacity(Self::SIZE);
+        bytes.push(Self::VERSION);
+        bytes.push(self.status);
+        for word in self.program_vkey.iter().chain(&self.aggregator_vkey) {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.dataset_root);
+        bytes.extend_from_slice(&encode_index(self.dataset_size));
+        bytes.extend_from_slice(&self.query_commitment);
+        bytes.extend_from_slice(&encode_index(self.num_results));
+        bytes.extend_from_slice(&encode_index(winner_idx));
+        bytes.extend_from_slice(&winner_commitment);
+        bytes.extend_from_slice(&winner_score.to_le_bytes());
+        bytes.extend_from_slice(&encode_optional_score(self.margin));
+        bytes.extend_from_slice(&self.final_digest);
+        bytes.extend_from_slice(&self.proofs_root);
+        bytes
+    }
+
+    /// Decodes the public values encoded with [`AggregatedOutputs::encode`].
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = Reader::new(bytes);
+
+        let version = reader.read_u8().ok_or(DecodeError::Truncated)?;
+        if version != Self::VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+
+        let status = reader.read_u8().ok_or(DecodeError::Truncated)?;
+        let program_vkey = read_vkey(&mut reader)?;
+        let aggregator_vkey = read_vkey(&mut reader)?;
+        let dataset_root = reader.read_array().ok_or(DecodeError::Truncated)?;
+        let dataset_size = reader.read_u32().ok_or(DecodeError::Truncated)? as usize;
+        let query_commitment = reader.read_array().ok_or(DecodeError::Truncated)?;
+        let num_results = reader.read_u32().ok_or(DecodeError::Truncated)? as usize;
+        let winner_idx = reader.read_u32().ok_or(DecodeError::Truncated)? as usize;
+        let winner_commitment = reader.read_array().ok_or(DecodeError::Truncated)?;
+        let winner_score = reader.read_i64().ok_or(DecodeError::Truncated)?;
+        let margin = decode_optional_score(reader.read_array().ok_or(DecodeError::Truncated)?)
+            .ok_or(DecodeError::InvalidScore)?;
+        let final_digest = reader.read_array().ok_or(DecodeError::Truncated)?;
+        let proofs_root = reader.read_array().ok_or(DecodeError::Truncated)?;
+
+        if !reader.is_empty() {
+            return Err(DecodeError::TrailingBytes);
+        }
+
+        Ok(Self {
+            status,
+            program_vkey,
+            aggregator_vkey,
+            dataset_root,
+            dataset_size,
+            query_commitment,
+            num_results,
+            winner: (num_results > 0).then_some((winner_idx, winner_commitment, winner_score)),
+            margin,
+            final_digest,
+            proofs_root,
+        })
+    }
+
+    /// Public values of an aggregation that has rejected its input with the given error, instead of
+    /// aborting, so that an aggregator above it rejects it in turn as it is not a node.
+    pub fn rejected(program_vkey: [u32; 8], aggregator_vkey: [u32; 8], err: VnnsError) -> Self {
+        Self {
+            status: err.code(),
+            program_vkey,
+            aggregator_vkey,
+            dataset_root: [0; 32],
+            dataset_size: 0,
+            query_commitment: [0; 32],
+            num_results: 0,
+            winner: None,
+            margin: None,
+            final_digest: [0; 32],
+            proofs_root: [0; 32],
+        }
+    }
+
+    /// Whether the aggregation program has rejected its input.
+    pub fn is_rejected(&self) -> bool {
+        self.status != STATUS_OK
+    }
+
+    /// Whether the proof with the given public values is one of the aggregated proofs, given its
+    /// inclusion proof within [`proofs_tree`].
+    pub fn is_aggregated(&self, public_values: &[u8], proof: &MerkleProof) -> bool {
+        let leaf = proof_hash(&self.program_vkey, &Sha256::digest(public_values).into());
+        proof.verify(&leaf, &self.proofs_root)
+    }
+}
+
+/// Public values committed by an aggregator below the root of the aggregation tree, i.e. the
+/// aggregation of a contiguous run of proofs, which are the same size for any number of proofs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AggregationNode {
+    /// Verifying key digest of the VNNS program, which every aggregated proof is verified against.
+    pub program_vkey: [u32; 8],
+    /// Verifying key digest that the aggregators below this one are verified against, i.e. the key
+    /// of the aggregation program itself.
+    pub aggregator_vkey: [u32; 8],
+    /// Digest of the search that every aggregated proof belongs to, see [`search_digest`].
+    pub search_digest: Hash,
+    /// Numbers of the aggregated proofs, in the order they were generated.
+    pub proofs: Range<usize>,
+    /// Height of the node within [`proofs_tree`], where a single proof is at height zero.
+    pub height: u32,
+    /// Root of the subtree of [`proofs_tree`] at this node, padded like the tree itself.
+    pub subtree_root: Hash,
+    /// Start of the range covered by the first aggregated proof.
+    pub first_start: usize,
+    /// Range covered by the last aggregated proof.
+    pub last_covered: Range<usize>,
+    /// End of every round that ends before the last aggregated proof, which is the size of the
+    /// dataset, or `None` if there is no such round.
+    pub round_end: Option<usize>,
+    /// SHA-256 digest of the public values of the last aggregated proof.
+    pub last_digest: Hash,
+}
+
+impl AggregationNode {
+    /// Version of the layout, which is distinct from [`AggregatedOutputs::VERSION`] so that the
+    /// root of an aggregation tree is never taken for a node.
+    pub const VERSION: u8 = 0x80 | AggregatedOutputs::VERSION;
+
+    /// Size of the encoded public values.
+    pub const SIZE: usize = 1 + 32 + 32 + 32 + 4 + 4 + 4 + 32 + 4 + 4 + 4 + 4 + 32;
+
+    /// Byte representation of the public values, to be committed by the aggregation program.
+    ///
+    /// ( version || program_vkey as u32s || aggregator_vkey as u32s || search_digest ||
+    /// proofs.start as u32 || proofs.end as u32 || height as u32 || subtree_root ||
+    /// first_start as u32 || last_covered.start as u32 || last_covered.end as u32 ||
+    /// round_end as u32 (zero if none) || last_digest ), where a round never ends at zero.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::SIZE);
+        bytes.push(Self::VERSION);
+        for word in self.program_vkey.iter().chain(&self.aggregator_vkey) {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.search_digest);
+        bytes.extend_from_slice(&encode_index(self.proofs.start));
+        bytes.extend_from_slice(&encode_index(self.proofs.end));
+        bytes.extend_from_slice(&self.height.to_le_bytes());
+        bytes.extend_from_slice(&self.subtree_root);
+        bytes.extend_from_slice(&encode_index(self.first_start));
+        bytes.extend_from_slice(&encode_index(self.last_covered.start));
+        bytes.extend_from_slice(&encode_index(self.last_covered.end));
+        bytes.extend_from_slice(&encode_index(self.round_end.unwrap_or(0)));
+        bytes.extend_from_slice(&self.last_digest);
+        bytes
+    }
+
+    /// Decodes the public values encoded with [`AggregationNode::encode`].
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = Reader::new(bytes);
+
+        let version = reader.read_u8().ok_or(DecodeError::Truncated)?;
+        if version != Self::VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+
+        let program_vkey = read_vkey(&mut reader)?;
+        let aggregator_vkey = read_vkey(&mut reader)?;
+        let search_digest = reader.read_array().ok_or(DecodeError::Truncated)?;
+        let proofs_start = reader.read_u32().ok_or(DecodeError::Truncated)? as usize;
+        let proofs_end = reader.read_u32().ok_or(DecodeError::Truncated)? as usize;
+        let height = reader.read_u32().ok_or(DecodeError::Truncated)?;
+        let subtree_root = reader.read_array().ok_or(DecodeError::Truncated)?;
+        let first_start = reader.read_u32().ok_or(DecodeError::Truncated)? as usize;
+        let last_start = reader.read_u32().ok_or(DecodeError::Truncated)? as usize;
+        let last_end = reader.read_u32().ok_or(DecodeError::Truncated)? as usize;
+        let round_end = reader.read_u32().ok_or(DecodeError::Truncated)? as usize;
+        let last_digest = reader.read_array().ok_or(DecodeError::Truncated)?;
+
+        if !reader.is_empty() {
+            return Err(DecodeError::TrailingBytes);
+        }
+
+        Ok(Self {
+            program_vkey,
+            aggregator_vkey,
+            search_digest,
+            proofs: proofs_start..proofs_end,
+            height,
+            subtree_root,
+            first_start,
+            last_covered: last_start..last_end,
+            round_end: (round_end > 0).then_some(round_end),
+            last_digest,
+        })
+    }
+}
+
+fn read_vkey(reader: &mut Reader) -> Result<[u32; 8], DecodeError> {
+    let mut vkey = [0u32; 8];
+    for word in &mut vkey {
+        *word = reader.read_u32().ok_or(DecodeError::Truncated)?;
+    }
+    Ok(vkey)
+}
+
+/// Merkle tree over the proofs with the given verifying key & public values, whose root is
+/// committed as [`AggregatedOutputs::proofs_root`], to create the inclusion proof of each.
+pub fn proofs_tree(vkey: [u32; 8], public_values: &[Vec<u8>]) -> MerkleTree {
+    MerkleTree::new(
+        public_values
+            .iter()
+            .map(|public_values| proof_hash(&vkey, &Sha256::digest(public_values).into()))
+            .collect(),
+    )
+}
+
+/// Digest of everything that the chunks of a search have in common, i.e. of the fields compared
+/// by [`PublicOutputs::is_same_search`].
+pub fn search_digest(outputs: &PublicOutputs) -> Hash {
+    let search = PublicOutputs {
+        status: STATUS_OK,
+        covered: 0..0,
+        results: Vec::new(),
+        scores: Vec::new(),
+        runner_up: None,
+        ..outputs.clone()
+    };
+    tagged_hash(Domain::Search, &search.encode())
+}
+
+/// Leaf nodes of the proofs with the given public values, which must already be verified against
+/// the given VNNS program, numbered from `start` in the order they were generated.
+///
+/// Every proof must be a successful chunk committed by the given program.
+pub fn proof_nodes(
+    program_vkey: [u32; 8],
+    aggregator_vkey: [u32; 8],
+    start: usize,
+    public_values: &[Vec<u8>],
+) -> Result<Vec<AggregationNode>, VnnsError> {
+    public_values
+        .iter()
+        .enumerate()
+        .map(|(idx, public_values)| {
+            let outputs = PublicOutputs::decode(public_values)
+                .map_err(|_| VnnsError::InvalidPreviousRound)?;
+            if outputs.is_rejected() || outputs.program_vkey != program_vkey {
+                return Err(VnnsError::InvalidPreviousRound);
+            }
+            let digest = Sha256::digest(public_values).into();
+            Ok(AggregationNode {
+                program_vkey,
+                aggregator_vkey,
+                search_digest: search_digest(&outputs),
+                proofs: start + idx..start + idx + 1,
+                height: 0,
+                subtree_root: proof_hash(&program_vkey, &digest),
+                first_start: outputs.covered.start,
+                last_covered: outputs.covered,
+                round_end: None,
+                last_digest: digest,
+            })
+        })
+        .collect()
+}
+
+/// Nodes committed by the aggregators with the given public values, which must already be verified
+/// against the given aggregator key, i.e. the key of the aggregation program itself.
+pub fn decode_nodes(
+    program_vkey: [u32; 8],
+    aggregator_vkey: [u32; 8],
+    public_values: &[Vec<u8>],
+) -> Result<Vec<AggregationNode>, VnnsError> {
+    public_values
+        .iter()
+        .map(|public_values| {
+            let node = AggregationNode::decode(public_values)
+                .map_err(|_| VnnsError::InvalidAggregationTree)?;
+            if node.program_vkey != program_vkey || node.aggregator_vkey != aggregator_vkey {
+                return Err(VnnsError::InvalidAggregationTree);
+            }
+            Ok(node)
+        })
+        .collect()
+}
+
+/// Merges the nodes of consecutive runs of proofs into their parent node at the given height.
+///
+/// The nodes must be of the same search & the same height, and aligned within [`proofs_tree`]:
+/// every node but the last one must be full, and the parent must fit them all. Within the rounds,
+/// each proof must either continue from the end of the previous one, or start over from the
+/// beginning of the dataset at the end of a round, which must be the same for all rounds.
+pub fn merge_nodes(
+    children: &[AggregationNode],
+    height: u32,
+) -> Result<AggregationNode, VnnsError> {
+    let (Some(first), Some(last)) = (children.first(), children.last()) else {
+        return Err(VnnsError::InvalidAggregationTree);
+    };
+    if children.iter().any(|child| {
+        child.program_vkey != first.program_vkey
+            || child.aggregator_vkey != first.aggregator_vkey
+            || child.search_digest != first.search_digest
+    }) {
+        return Err(VnnsError::InvalidPreviousRound);
+    }
+
+    // the children are the consecutive subtrees of the parent, starting at its first leaf
+    let child_size = 1usize
+        .checked_shl(first.height)
+        .ok_or(VnnsError::InvalidAggregationTree)?;
+    let levels = height
+        .checked_sub(first.height)
+        .ok_or(VnnsError::InvalidAggregationTree)?;
+    let size = 1usize
+        .checked_shl(height)
+        .ok_or(VnnsError::InvalidAggregationTree)?;
+    if children.len() > size / child_size
+        || first.proofs.start % size != 0
+        || children.iter().any(|child| {
+            child.height != first.height
+                || child.proofs.is_empty()
+                || child.proofs.len() > child_size
+        })
+        || children.windows(2).any(|pair| {
+            pair[0].proofs.len() != child_size || pair[0].proofs.end != pair[1].proofs.start
+        })
+    {
+        return Err(VnnsError::InvalidAggregationTree);
+    }
+
+    // each round starts over from the beginning of the dataset, once the previous one has ended
+    let mut round_end = None;
+    let mut end_round = |end: usize| match round_end {
+        Some(round_end) if round_end != end => Err(VnnsError::NonContiguousChunk),
+        _ => {
+            round_end = Some(end);
+            Ok(())
+        }
+    };
+    for child in children {
+        if child.last_covered.is_empty() {
+            return Err(VnnsError::NonContiguousChunk);
+        }
+        if let Some(end) = child.round_end {
+            end_round(end)?;
+        }
+    }
+    for pair in children.windows(2) {
+        let end = pair[0].last_covered.end;
+        if pair[1].first_start == 0 {
+            end_round(end)?;
+        } else if pair[1].first_start != end {
+            return Err(VnnsError::NonContiguousChunk);
+        }
+    }
+
+    let roots = children
+        .iter()
+        .map(|child| child.subtree_root)
+        .collect::<Vec<_>>();
+    Ok(AggregationNode {
+        program_vkey: first.program_vkey,
+        aggregator_vkey: first.aggregator_vkey,
+        search_digest: first.search_digest,
+        proofs: first.proofs.start..last.proofs.end,
+        height,
+        subtree_root: subtree_root(&roots, empty_root(first.height), levels),
+        first_start: first.first_start,
+        last_covered: last.last_covered.clone(),
+        round_end,
+        last_digest: last.last_digest,
+    })
+}
+
+/// Merges the nodes of all proofs into the root of the aggregation tree, and returns the result
+/// of the search, given the public values of the final proof.
+///
+/// The root is as high as its children need, like [`proofs_tree`] itself, so its subtree root is
+/// the top node of that tree. The rounds must cover the dataset one after another, and the last
+/// round must consist of a single, final proof that covers the whole dataset, up to the size that
+/// every proof commits to.
+pub fn aggregate_root(
+    children: &[AggregationNode],
+    final_public_values: &[u8],
+) -> Result<AggregatedOutputs, VnnsError> {
+    let Some(first) = children.first() else {
+        return Err(VnnsError::InvalidPreviousRound);
+    };
+    // a single node would be the root of a tree that may be higher than the proofs need
+    if children.len() == 1 && first.height > 0 {
+        return Err(VnnsError::InvalidAggregationTree);
+    }
+    let levels = children.len().next_power_of_two().trailing_zeros();
+    let root = merge_nodes(children, first.height + levels)?;
+    if root.proofs.start != 0 {
+        return Err(VnnsError::InvalidAggregationTree);
+    }
+
+    let last =
+        PublicOutputs::decode(final_public_values).map_err(|_| VnnsError::InvalidPreviousRound)?;
+    let final_digest: Hash = Sha256::digest(final_public_values).into();
+    if final_digest != root.last_digest {
+        return Err(VnnsError::InvalidPreviousRound);
+    }
+
+    // every round ends with the dataset size that its root commits to
+    let dataset_size = last.dataset_size;
+    if root.first_start != 0
+        || !last.covers_dataset()
+        || root.round_end.is_some_and(|end| end != dataset_size)
+    {
+        return Err(VnnsError::NonContiguousChunk);
+    }
+
+    Ok(AggregatedOutputs {
+        status: STATUS_OK,
+        program_vkey: root.program_vkey,
+        aggregator_vkey: root.aggregator_vkey,
+        dataset_root: last.dataset_root,
+        dataset_size,
+        query_commitment: last.query_commitment,
+        num_results: last.results.len(),
+        winner: last
+            .results
+            .first()
+            .map(|&(idx, output_commitment)| (idx, output_commitment, last.scores[0])),
+        margin: last.margin(),
+        final_digest,
+        proofs_root: root_hash(&root.subtree_root, root.proofs.len()),
+    })
+}
+
+/// Checks that the public values of the proofs, which must already be verified against the given
+/// verifying key, form the rounds of a single search, and returns its result as aggregated at
+/// once, with the given aggregator key.
+///
+/// Every proof must be a successful chunk of the same search, committed by the given program. The
+/// chunks of each round must cover the dataset one after another, and the last round must consist
+/// of a single, final proof that covers the whole dataset.
+pub fn aggregate_outputs(
+    program_vkey: [u32; 8],
+    aggregator_vkey: [u32; 8],
+    public_values: &[Vec<u8>],
+) -> Result<AggregatedOutputs, VnnsError> {
+    let nodes = proof_nodes(program_vkey, aggregator_vkey, 0, public_values)?;
+    let Some(final_public_values) = public_values.last() else {
+        return Err(VnnsError::InvalidPreviousRound);
+    };
+    aggregate_root(&nodes, final_public_values)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Range;
+
+    use super::*;
+    use crate::outputs::test_chunk;
+
+    /// Public values of a chunk over a dataset of the given size, see [`test_chunk`].
+    fn chunk(dataset_size: usize, covered: Range<usize>, winners: &[usize]) -> Vec<u8> {
+        PublicOutputs {
+            dataset_size,
+            ..test_chunk(covered, winners)
+        }
+        .encode()
+    }
+
+    #[test]
+    fn test_aggregate_outputs() {
+        // two rounds over 6 samples
+        let proofs = vec![
+            chunk(6, 0..3, &[1]),
+            chunk(6, 3..6, &[5]),
+            chunk(6, 0..6, &[5]),
+        ];
+        let aggregated = aggregate_outputs([3; 8], [9; 8], &proofs).unwrap();
+        assert_eq!(
+            aggregated,
+            AggregatedOutputs {
+                status: STATUS_OK,
+                program_vkey: [3; 8],
+                aggregator_vkey: [9; 8],
+                dataset_root: [2; 32],
+                dataset_size: 6,
+                query_commitment: [1; 32],
+                num_results: 1,
+                winner: Some((5, [5; 32], 5)),
+                margin: Some(1),
+                final_digest: Sha256::digest(&proofs[2]).into(),
+                proofs_root: proofs_tree([3; 8], &proofs).root(),
+            }
+        );
+
+        // each proof is part of the aggregation by its inclusion proof, at its own index only
+        let tree = proofs_tree([3; 8], &proofs);
+        for (idx, public_values) in proofs.iter().enumerate() {
+            assert!(aggregated.is_aggregated(public_values, &tree.proof(idx)));
+        }
+        assert!(!aggregated.is_aggregated(&proofs[0], &tree.proof(1)));
+        assert!(!aggregated.is_aggregated(&chunk(6, 0..6, &[4]), &tree.proof(2)));
+
+        assert!(!aggregated.is_rejected());
+        let bytes = aggregated.encode();
+        assert_eq!(bytes.len(), AggregatedOutputs::SIZE);
+        assert_eq!(AggregatedOutputs::decode(&bytes), Ok(aggregated));
+        let mut previous_version = bytes.clone();
+        previous_version[0] = 4;
+        assert_eq!(
+            AggregatedOutputs::decode(&previous_version),
+            Err(DecodeError::UnsupportedVersion(4))
+        );
+
+        // a rejected aggregation is committed at the same size, and is never taken for a node
+        let rejected = AggregatedOutputs::rejected([3; 8], [9; 8], VnnsError::NonContiguousChunk);
+        assert!(rejected.is_rejected());
+        let bytes = rejected.encode();
+        assert_eq!(bytes.len(), AggregatedOutputs::SIZE);
+        assert_eq!(AggregatedOutputs::decode(&bytes), Ok(rejected));
+        assert_eq!(
+            decode_nodes([3; 8], [9; 8], &[bytes]),
+            Err(VnnsError::InvalidAggregationTree)
+        );
+
+        // a single proof over the whole dataset, without results
+        let aggregated = aggregate_outputs([3; 8], [9; 8], &[chunk(6, 0..6, &[])]).unwrap();
+        assert_eq!(aggregated.winner, None);
+        assert_eq!(
+            AggregatedOutputs::decode(&aggregated.encode()),
+            Ok(aggregated)
+        );
+    }
+
+    /// Aggregates the proofs by a tree with the given fan-out, through the encoding of each node.
+    fn aggregate_tree(proofs: &[Vec<u8>], fan_out: usize) -> Result<AggregatedOutputs, VnnsError> {
+        let mut nodes = proof_nodes([3; 8], [9; 8], 0, proofs)?;
+        let mut height = 0;
+        while nodes.len() > fan_out {
+            height += fan_out.trailing_zeros();
+            let public_values = nodes
+                .chunks(fan_out)
+                .map(|children| merge_nodes(children, height).map(|node| node.encode()))
+                .collect::<Result<Vec<_>, _>>()?;
+            nodes = decode_nodes([3; 8], [9; 8], &public_values)?;
+        }
+        aggregate_root(&nodes, proofs.last().unwrap())
+    }
+
+    #[test]
+    fn test_aggregation_tree() {
+        // three rounds over 8 samples, in 7 proofs
+        let proofs = vec![
+            chunk(8, 0..2, &[1]),
+            chunk(8, 2..4, &[2]),
+            chunk(8, 4..6, &[5]),
+            chunk(8, 6..8, &[6]),
+            chunk(8, 0..4, &[2]),
+            chunk(8, 4..8, &[5]),
+            chunk(8, 0..8, &[5]),
+        ];
+        let aggregated = aggregate_outputs([3; 8], [9; 8], &proofs).unwrap();
+        assert_eq!(aggregated.proofs_root, proofs_tree([3; 8], &proofs).root());
+        for fan_out in [2, 4, 8] {
+            assert_eq!(aggregate_tree(&proofs, fan_out), Ok(aggregated.clone()));
+        }
+
+        // nodes are committed at a fixed size, and are never taken for the root
+        let nodes = proof_nodes([3; 8], [9; 8], 0, &proofs).unwrap();
+        let node = merge_nodes(&nodes[4..], 2).unwrap();
+        assert_eq!(node.proofs, 4..7);
+        assert_eq!(node.round_end, Some(8));
+        let bytes = node.encode();
+        assert_eq!(bytes.len(), AggregationNode::SIZE);
+        assert_eq!(AggregationNode::decode(&bytes), Ok(node));
+        assert_eq!(
+            AggregationNode::decode(&aggregated.encode()),
+            Err(DecodeError::UnsupportedVersion(AggregatedOutputs::VERSION))
+        );
+
+        // a missing chunk is caught within the node that lacks it
+        let mut missing = proofs.clone();
+        missing.remove(1);
+        assert_eq!(
+            aggregate_tree(&missing, 2),
+            Err(VnnsError::NonContiguousChunk)
+        );
+    }
+
+    #[test]
+    fn test_aggregation_tree_errors() {
+        let proofs = (0..5)
+            .map(|i| chunk(5, i..i + 1, &[i]))
+            .chain([chunk(5, 0..5, &[4])])
+            .collect::<Vec<_>>();
+        let nodes = proof_nodes([3; 8], [9; 8], 0, &proofs).unwrap();
+        assert!(aggregate_tree(&proofs, 2).is_ok());
+
+        // misaligned, not full before the last one, or too many for the parent
+        assert_eq!(
+            merge_nodes(&nodes[1..3], 1),
+            Err(VnnsError::InvalidAggregationTree)
+        );
+        let pairs = [
+            merge_nodes(&nodes[..1], 1).unwrap(),
+            merge_nodes(&nodes[2..4], 1).unwrap(),
+        ];
+        assert_eq!(
+            merge_nodes(&pairs, 2),
+            Err(VnnsError::InvalidAggregationTree)
+        );
+        assert_eq!(
+            merge_nodes(&nodes[..4], 1),
+            Err(VnnsError::InvalidAggregationTree)
+        );
+
+        // a root over a single node, or over part of the proofs only
+        let quads = [
+            merge_nodes(&nodes[..4], 2).unwrap(),
+            merge_nodes(&nodes[4..], 2).unwrap(),
+        ];
+        let eights = merge_nodes(&quads, 3).unwrap();
+        assert_eq!(
+            aggregate_root(&[eights], &proofs[5]),
+            Err(VnnsError::InvalidAggregationTree)
+        );
+        assert_eq!(
+            aggregate_root(&quads[1..], &proofs[5]),
+            Err(VnnsError::InvalidAggregationTree)
+        );
+        assert!(aggregate_root(&quads, &proofs[5]).is_ok());
+
+        // the final public values must be the ones of the last proof
+        assert_eq!(
+            aggregate_root(&quads, &proofs[4]),
+            Err(VnnsError::InvalidPreviousRound)
+        );
+
+        // nodes of another aggregator, or of another search
+        let public_values = vec![quads[0].encode()];
+        assert_eq!(
+            decode_nodes([3; 8], [8; 8], &public_values),
+            Err(VnnsError::InvalidAggregationTree)
+        );
+        let mut other = proofs.clone();
+        other[4] = PublicOutputs {
+            metric_id: 1,
+            ..PublicOutputs::decode(&proofs[4]).unwrap()
+        }
+        .encode();
+        assert_eq!(
+            aggregate_tree(&other, 2),
+            Err(VnnsError::InvalidPreviousRound)
+        );
+    }
+
+    #[test]
+    fn test_aggregate_outputs_errors() {
+        // another program, or no proofs at all
+        let proofs = vec![chunk(6, 0..6, &[5])];
+        assert_eq!(
+            aggregate_outputs([4; 8], [9; 8], &proofs),
+            Err(VnnsError::InvalidPreviousRound)
+        );
+        assert_eq!(
+            aggregate_outputs([3; 8], [9; 8], &[]),
+            Err(VnnsError::InvalidPreviousRound)
+        );
+
+        // a chunk is missing, or the final proof does not cover the whole dataset
+        let proofs = vec![chunk(6, 0..3, &[1]), chunk(6, 0..6, &[5])];
+        assert_eq!(
+            aggregate_outputs([3; 8], [9; 8], &proofs),
+            Err(VnnsError::NonContiguousChunk)
+        );
+        let proofs = vec![chunk(6, 0..3, &[1]), chunk(6, 3..6, &[5])];
+        assert_eq!(
+            aggregate_outputs([3; 8], [9; 8], &proofs),
+            Err(VnnsError::NonContiguousChunk)
+        );
+
+        // the rounds end before the size that the dataset root commits to
+        let proofs = vec![
+            chunk(8, 0..3, &[1]),
+            chunk(8, 3..6, &[5]),
+            chunk(8, 0..6, &[5]),
+        ];
+        assert_eq!(
+            aggregate_outputs([3; 8], [9; 8], &proofs),
+            Err(VnnsError::NonContiguousChunk)
+        );
+    }
+}
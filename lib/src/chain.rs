@@ -0,0 +1,279 @@
+//! Links the samples of a chunk back to the dataset, so that the rounds of a tournament form a
+//! chain from the dataset root to the final results.
+//!
+//! The samples of a chunk within the first round are consecutive samples of the dataset, each with
+//! its inclusion proof. The samples of a chunk within a later round are the winners of consecutive
+//! chunks of the previous round, whose proofs are verified by the program itself. Either way, the
+//! chunk covers a contiguous range of the dataset, and the final chunk covers all of it.
+
+use std::ops::Range;
+
+use crate::encoding::item_hash;
+use crate::{
+    verify_inclusion, Element, EmbeddingMatrix, Hash, MerkleProof, Metadata, PublicOutputs, Score,
+    VnnsError,
+};
+
+/// Samples of a chunk that are linked to the dataset.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LinkedChunk {
+    /// Range of dataset indices that the chunk accounts for.
+    pub covered: Range<usize>,
+    /// Dataset index & hash of each sample, in the same order as the samples.
+    pub samples: Vec<(usize, Hash)>,
+    /// Closest score among the samples that the previous rounds have eliminated, or `None` within
+    /// the first round, so that the runner-up of the final chunk is the one of the whole dataset.
+    pub runner_up: Option<Score>,
+}
+
+/// Links the samples of a chunk within the first round, which must be consecutive samples of the
+/// dataset with the root & the size of the given search, quantized with its parameters if any.
+pub fn link_to_dataset<T: Element>(
+    samples: &EmbeddingMatrix<T>,
+    metadata: &[Metadata],
+    payloads: &[Hash],
+    search: &PublicOutputs,
+    proofs: &[MerkleProof],
+) -> Result<LinkedChunk, VnnsError> {
+    let hashes = verify_inclusion(
+        samples,
+        search.quantization.as_ref(),
+        metadata,
+        payloads,
+        &search.dataset_root,
+        proofs,
+    )?;
+    // the root commits to the number of leaves, which must be the committed size of the dataset
+    if let Some(idx) = proofs[..hashes.len()]
+        .iter()
+        .position(|proof| proof.num_leaves != search.dataset_size)
+    {
+        return Err(VnnsError::InvalidInclusionProof { idx });
+    }
+    let start = proofs.first().map(|proof| proof.idx).unwrap_or(0);
+    if proofs[..hashes.len()]
+        .iter()
+        .enumerate()
+        .any(|(i, proof)| proof.idx != start + i)
+    {
+        return Err(VnnsError::NonContiguousChunk);
+    }
+
+    Ok(LinkedChunk {
+        covered: start..start + hashes.len(),
+        samples: hashes
+            .into_iter()
+            .enumerate()
+            .map(|(i, hash)| (start + i, hash))
+            .collect(),
+        runner_up: None,
+    })
+}
+
+/// Links the samples of a chunk within a later round, which must be the winners of the chunks of
+/// the previous round with the given public values, in dataset order.
+///
+/// The public values must already be verified against `search.program_vkey`, and each of them must
+/// be a successful chunk of the same search, with their covered ranges following one another.
+pub fn link_to_previous_round<T: Element>(
+    samples: &EmbeddingMatrix<T>,
+    metadata: &[Metadata],
+    payloads: &[Hash],
+    search: &PublicOutputs,
+    previous: &[Vec<u8>],
+) -> Result<LinkedChunk, VnnsError> {
+    if metadata.len() != samples.len() {
+        return Err(VnnsError::InvalidMetadata);
+    }
+    if payloads.len() != samples.len() {
+        return Err(VnnsError::InvalidPayloads);
+    }
+
+    let previous = previous
+        .iter()
+        .map(|public_values| PublicOutputs::decode(public_values))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| VnnsError::InvalidPreviousRound)?;
+    if previous.is_empty()
+        || previous
+            .iter()
+            .any(|outputs| outputs.is_rejected() || !outputs.is_same_search(search))
+    {
+        return Err(VnnsError::InvalidPreviousRound);
+    }
+    if previous
+        .windows(2)
+        .any(|pair| pair[0].covered.end != pair[1].covered.start)
+    {
+        return Err(VnnsError::NonContiguousChunk);
+    }
+
+    // the chunks are consecutive, so their winners are in dataset order when sorted within each
+    let mut winners = Vec::new();
+    for outputs in &previous {
+        let mut chunk_winners = outputs.results.clone();
+        chunk_winners.sort_by_key(|(idx, _)| *idx);
+        winners.extend(chunk_winners);
+    }
+    if winners.len() != samples.len() {
+        return Err(VnnsError::InvalidPreviousRound);
+    }
+    let quantization = search.quantization.as_ref();
+    for (idx, (sample, (_, hash))) in samples.rows().zip(&winners).enumerate() {
+        if item_hash(sample, quantization, &metadata[idx], &payloads[idx]) != *hash {
+            return Err(VnnsError::NotPreviousWinner { idx });
+        }
+    }
+
+    Ok(LinkedChunk {
+        covered: previous[0].covered.start..previous[previous.len() - 1].covered.end,
+        samples: winners,
+        runner_up: previous
+            .iter()
+            .filter_map(|outputs| outputs.runner_up)
+            .min(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::outputs::test_chunk;
+    use crate::MerkleTree;
+
+    fn dataset() -> (EmbeddingMatrix<i16>, Vec<Metadata>, MerkleTree) {
+        let samples = EmbeddingMatrix::new(1, (0..6).collect()).unwrap();
+        let metadata = vec![Metadata::default(); 6];
+        let tree = MerkleTree::new(
+            samples
+                .rows()
+                .map(|row| item_hash(row, None, &Metadata::default(), &[0; 32]))
+                .collect(),
+        );
+        (samples, metadata, tree)
+    }
+
+    /// Public values of a chunk over the dataset, see [`test_chunk`].
+    fn chunk(tree: &MerkleTree, covered: Range<usize>, winners: &[usize]) -> PublicOutputs {
+        PublicOutputs {
+            dataset_root: tree.root(),
+            dataset_size: tree.num_leaves(),
+            results: winners.iter().map(|&idx| (idx, tree.leaf(idx))).collect(),
+            ..test_chunk(covered, winners)
+        }
+    }
+
+    #[test]
+    fn test_link_to_dataset() {
+        let (samples, metadata, tree) = dataset();
+        let search = chunk(&tree, 0..0, &[]);
+        let proofs = (2..5).map(|idx| tree.proof(idx)).collect::<Vec<_>>();
+        assert_eq!(
+            link_to_dataset(
+                &samples.select(&[2, 3, 4]),
+                &metadata[..3],
+                &[[0; 32]; 3],
+                &search,
+                &proofs
+            ),
+            Ok(LinkedChunk {
+                covered: 2..5,
+                samples: (2..5).map(|idx| (idx, tree.leaf(idx))).collect(),
+                runner_up: None,
+            })
+        );
+
+        // a gap within the chunk
+        let proofs = vec![tree.proof(2), tree.proof(4)];
+        assert_eq!(
+            link_to_dataset(
+                &samples.select(&[2, 4]),
+                &metadata[..2],
+                &[[0; 32]; 2],
+                &search,
+                &proofs
+            ),
+            Err(VnnsError::NonContiguousChunk)
+        );
+
+        // the same samples within a search that commits to another dataset size
+        let resized = PublicOutputs {
+            dataset_size: 8,
+            ..search
+        };
+        assert_eq!(
+            link_to_dataset(
+                &samples.select(&[2]),
+                &metadata[..1],
+                &[[0; 32]],
+                &resized,
+                &[tree.proof(2)]
+            ),
+            Err(VnnsError::InvalidInclusionProof { idx: 0 })
+        );
+    }
+
+    #[test]
+    fn test_link_to_previous_round() {
+        let (samples, metadata, tree) = dataset();
+        let search = chunk(&tree, 0..0, &[]);
+        let previous = vec![
+            chunk(&tree, 0..3, &[2, 0]).encode(),
+            chunk(&tree, 3..6, &[4]).encode(),
+        ];
+        let link = |idxs: &[usize], previous: &[Vec<u8>]| {
+            link_to_previous_round(
+                &samples.select(idxs),
+                &metadata[..idxs.len()],
+                &vec![[0; 32]; idxs.len()],
+                &search,
+                previous,
+            )
+        };
+
+        assert_eq!(
+            link(&[0, 2, 4], &previous),
+            Ok(LinkedChunk {
+                covered: 0..6,
+                samples: [0, 2, 4].iter().map(|&idx| (idx, tree.leaf(idx))).collect(),
+                runner_up: Some(3),
+            })
+        );
+
+        // another sample, a missing one, or the winners in another order
+        assert_eq!(
+            link(&[0, 3, 4], &previous),
+            Err(VnnsError::NotPreviousWinner { idx: 1 })
+        );
+        assert_eq!(
+            link(&[0, 2], &previous),
+            Err(VnnsError::InvalidPreviousRound)
+        );
+        assert_eq!(
+            link(&[2, 0, 4], &previous),
+            Err(VnnsError::NotPreviousWinner { idx: 0 })
+        );
+
+        // chunks with a gap, of another search, or rejected
+        let gap = vec![previous[0].clone(), chunk(&tree, 4..6, &[4]).encode()];
+        assert_eq!(link(&[0, 2, 4], &gap), Err(VnnsError::NonContiguousChunk));
+        let other_search = PublicOutputs {
+            query_commitment: [3; 32],
+            ..chunk(&tree, 3..6, &[4])
+        };
+        let mixed = vec![previous[0].clone(), other_search.encode()];
+        assert_eq!(
+            link(&[0, 2, 4], &mixed),
+            Err(VnnsError::InvalidPreviousRound)
+        );
+        let rejected = PublicOutputs {
+            status: VnnsError::EmptySamples.code(),
+            ..chunk(&tree, 3..6, &[])
+        };
+        assert_eq!(
+            link(&[0, 2], &[previous[0].clone(), rejected.encode()]),
+            Err(VnnsError::InvalidPreviousRound)
+        );
+        assert_eq!(link(&[], &[]), Err(VnnsError::InvalidPreviousRound));
+    }
+}
@@ -0,0 +1,350 @@
+//! Canonical byte encoding of the values that are hashed or committed, shared by the embedder, the
+//! program & the script.
+//!
+//! All integers & floats are little-endian regardless of the machine, lengths & indices are `u32`,
+//! and every hash is prefixed with a [`Domain`] tag so that the hash of one kind of value can never
+//! be taken for another, e.g. an item for an inner node of the dataset tree.
+
+use sha2::{Digest, Sha256};
+
+use crate::{Element, Hash, Metadata, Quantization, Score};
+
+/// Domain separation tag, the first byte of every hashed message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Domain {
+    /// An item of the dataset, i.e. a leaf of the dataset tree.
+    Item = 0,
+    /// An inner node of the dataset tree.
+    Node = 1,
+    /// A query vector.
+    Query = 2,
+    /// A proof within the aggregation tree, i.e. its verifying key & the digest of its public
+    /// values.
+    Proof = 3,
+    /// The payload of an item, i.e. the raw data that was embedded.
+    Payload = 4,
+    /// A query vector along with a secret salt.
+    SaltedQuery = 5,
+    /// The search of a proof, i.e. its public values without the status, the covered range & the
+    /// results.
+    Search = 6,
+    /// The root of a tree along with its number of leaves, i.e. the root that is committed.
+    Root = 7,
+}
+
+/// Hashes the message within the given domain.
+///
+/// SHA256( domain || message )
+pub fn tagged_hash(domain: Domain, message: &[u8]) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([domain as u8]);
+    hasher.update(message);
+    hasher.finalize().into()
+}
+
+/// Encodes an index or a length.
+///
+/// ( value as u32 )
+pub fn encode_index(idx: usize) -> [u8; 4] {
+    u32::try_from(idx)
+        .expect("index does not fit into u32")
+        .to_le_bytes()
+}
+
+/// Encodes an optional score, where the score is zero if there is none.
+///
+/// ( is_some as u8 || score as i64 )
+pub fn encode_optional_score(score: Option<Score>) -> [u8; 9] {
+    let mut bytes = [0u8; 9];
+    if let Some(score) = score {
+        bytes[0] = 1;
+        bytes[1..].copy_from_slice(&score.to_le_bytes());
+    }
+    bytes
+}
+
+/// Decodes an optional score encoded with [`encode_optional_score`], or `None` if it is malformed.
+pub(crate) fn decode_optional_score(bytes: [u8; 9]) -> Option<Option<Score>> {
+    let score = Score::from_le_bytes(bytes[1..].try_into().expect("expected 8 bytes"));
+    match bytes[0] {
+        0 if score == 0 => Some(None),
+        1 => Some(Some(score)),
+        _ => None,
+    }
+}
+
+/// Encodes a vector along with its length.
+///
+/// ( len as u32 || values[0] || values[1] || ... )
+pub fn encode_vector<T: Element>(values: &[T]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(4 + values.len() * T::SIZE);
+    bytes.extend_from_slice(&encode_index(values.len()));
+    for value in values {
+        value.write_le_bytes(&mut bytes);
+    }
+    bytes
+}
+
+/// Encodes an item, i.e. its embedding vector, its metadata & the hash of its payload, along with
+/// the quantization parameters if the vector is quantized.
+///
+/// ( vector || metadata || payload || quantization ), where the quantization is omitted if there
+/// is none, see [`encode_vector`], [`Metadata::to_bytes`], [`payload_hash`] &
+/// [`Quantization::to_bytes`].
+pub fn encode_item<T: Element>(
+    values: &[T],
+    quantization: Option<&Quantization>,
+    metadata: &Metadata,
+    payload: &Hash,
+) -> Vec<u8> {
+    let mut bytes = encode_vector(values);
+    bytes.extend_from_slice(&metadata.to_bytes());
+    bytes.extend_from_slice(payload);
+    if let Some(quantization) = quantization {
+        bytes.extend_from_slice(&quantization.to_bytes());
+    }
+    bytes
+}
+
+/// Hash of an item, which is the `hash` of each item within the index, the leaf of the dataset
+/// tree, and the output commitment of a result. Items with the same embedding but another payload
+/// have different hashes, and so do quantized items with the same levels but another scale.
+pub fn item_hash<T: Element>(
+    values: &[T],
+    quantization: Option<&Quantization>,
+    metadata: &Metadata,
+    payload: &Hash,
+) -> Hash {
+    tagged_hash(
+        Domain::Item,
+        &encode_item(values, quantization, metadata, payload),
+    )
+}
+
+/// Hash of the payload of an item, in whichever encoding the embedder has stored it.
+pub fn payload_hash(payload: &[u8]) -> Hash {
+    tagged_hash(Domain::Payload, payload)
+}
+
+/// Hash of an inner node of the dataset tree.
+pub fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut message = [0u8; 64];
+    message[..32].copy_from_slice(left);
+    message[32..].copy_from_slice(right);
+    tagged_hash(Domain::Node, &message)
+}
+
+/// Hash of the top node of a tree along with its number of leaves without the padding, i.e. the
+/// root of the tree, so that the root commits to the size of the dataset as well.
+///
+/// The message is ( node || num_leaves as u32 ).
+pub fn root_hash(node: &Hash, num_leaves: usize) -> Hash {
+    let mut message = [0u8; 36];
+    message[..32].copy_from_slice(node);
+    message[32..].copy_from_slice(&encode_index(num_leaves));
+    tagged_hash(Domain::Root, &message)
+}
+
+/// Hash of a query vector, i.e. the query commitment.
+pub fn query_hash<T: Element>(values: &[T]) -> Hash {
+    tagged_hash(Domain::Query, &encode_vector(values))
+}
+
+/// Hash of a query vector along with a secret salt, i.e. a hiding query commitment that can not be
+/// checked against candidate queries without the salt.
+///
+/// The message is ( salt || vector ), see [`encode_vector`].
+pub fn salted_query_hash<T: Element>(salt: &Hash, values: &[T]) -> Hash {
+    let mut message = salt.to_vec();
+    message.extend_from_slice(&encode_vector(values));
+    tagged_hash(Domain::SaltedQuery, &message)
+}
+
+/// Query commitment of a query vector, which is salted if a salt is given.
+pub fn query_commitment<T: Element>(values: &[T], salt: Option<&Hash>) -> Hash {
+    match salt {
+        Some(salt) => salted_query_hash(salt, values),
+        None => query_hash(values),
+    }
+}
+
+/// Hash of a proof, i.e. a leaf of the aggregation tree.
+///
+/// The message is ( vkey as u32s || public_values_digest ), where the digest is the SHA-256 of the
+/// public values, the same as the one the proof is verified with.
+pub fn proof_hash(vkey: &[u32; 8], public_values_digest: &Hash) -> Hash {
+    let mut message = [0u8; 64];
+    for (bytes, word) in message.chunks_exact_mut(4).zip(vkey) {
+        bytes.copy_from_slice(&word.to_le_bytes());
+    }
+    message[32..].copy_from_slice(public_values_digest);
+    tagged_hash(Domain::Proof, &message)
+}
+
+/// Appends a string along with its length.
+///
+/// ( len as u32 || utf-8 bytes )
+pub(crate) fn write_str(bytes: &mut Vec<u8>, s: &str) {
+    bytes.extend_from_slice(&encode_index(s.len()));
+    bytes.extend_from_slice(s.as_bytes());
+}
+
+/// Reads little-endian values from bytes, returning `None` once the bytes are exhausted.
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn read_slice(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.bytes.len() < len {
+            return None;
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Some(head)
+    }
+
+    pub fn read_u8(&mut self) -> Option<u8> {
+        self.read_slice(1).map(|bytes| bytes[0])
+    }
+
+    pub fn read_array<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let bytes = self.read_slice(N)?;
+        Some(bytes.try_into().expect("expected N bytes"))
+    }
+
+    pub fn read_u32(&mut self) -> Option<u32> {
+        let bytes = self.read_slice(4)?;
+        Some(u32::from_le_bytes(
+            bytes.try_into().expect("expected 4 bytes"),
+        ))
+    }
+
+    pub fn read_i64(&mut self) -> Option<i64> {
+        let bytes = self.read_slice(8)?;
+        Some(i64::from_le_bytes(
+            bytes.try_into().expect("expected 8 bytes"),
+        ))
+    }
+
+    pub fn read_str(&mut self) -> Option<String> {
+        let len = self.read_u32()? as usize;
+        let bytes = self.read_slice(len)?;
+        String::from_utf8(bytes.to_vec()).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::AttributeValue;
+
+    // golden vectors, any change to these breaks the hashes of existing indices & proofs
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode_index(258), [2, 1, 0, 0]);
+        assert_eq!(
+            hex::encode(encode_vector(&[1.0f32, -2.0])),
+            "020000000000803f000000c0"
+        );
+        assert_eq!(hex::encode(encode_vector(&[1i16, -1])), "020000000100ffff");
+        assert_eq!(
+            hex::encode(encode_item(
+                &[1i16],
+                None,
+                &Metadata::default(),
+                &[0xdd; 32]
+            )),
+            format!("01000000010000000000{}", "dd".repeat(32))
+        );
+        let quantization = Quantization::new(8, 1.0);
+        assert_eq!(
+            hex::encode(encode_item(
+                &[1i16],
+                Some(&quantization),
+                &Metadata::default(),
+                &[0xdd; 32]
+            )),
+            format!("01000000010000000000{}080000803f", "dd".repeat(32))
+        );
+
+        assert_eq!(
+            hex::encode(encode_optional_score(Some(-2))),
+            "01feffffffffffffff"
+        );
+        assert_eq!(encode_optional_score(None), [0; 9]);
+        for score in [Some(-2), None] {
+            assert_eq!(
+                decode_optional_score(encode_optional_score(score)),
+                Some(score)
+            );
+        }
+        assert_eq!(decode_optional_score([2; 9]), None);
+    }
+
+    #[test]
+    fn test_hash() {
+        let payload = payload_hash(br#"{"name":"apple"}"#);
+        assert_eq!(
+            hex::encode(payload),
+            "4d11ba866999d059ded1a832861eb347ff0218b298d1b7089520fc4940111e99"
+        );
+        assert_eq!(
+            hex::encode(item_hash(
+                &[1.0f32, -2.0],
+                None,
+                &Metadata::default(),
+                &payload
+            )),
+            "b4e9a1cbb5e09489746d4b93893720a19f20e0962d9f52577ee0dddc00911388"
+        );
+
+        let metadata = Metadata(BTreeMap::from([(
+            "price".to_string(),
+            AttributeValue::Number(100),
+        )]));
+        assert_eq!(
+            hex::encode(item_hash(&[1.0f32, -2.0], None, &metadata, &payload)),
+            "d2309879855314561fab75b01cf2414ab8e9eaa62172c8312bed1c819c5a689d"
+        );
+
+        assert_eq!(
+            hex::encode(node_hash(&[0xaa; 32], &[0xbb; 32])),
+            "2f65cc0c7abfdb0c535cb7f942d65ae1fb04c9a3ad3ea5a62057aa8ac934a93a"
+        );
+        assert_eq!(
+            hex::encode(root_hash(&[0xaa; 32], 3)),
+            "636a4bf644135f8d2680fdccfdd136cb98d991473c36dac52c8c0c5dfad107ff"
+        );
+        assert_ne!(root_hash(&[0xaa; 32], 3), root_hash(&[0xaa; 32], 4));
+        assert_eq!(
+            hex::encode(query_hash(&[1i16, -1])),
+            "5f4db2f8d05184e3f37c236cfeed2d650590056c89863a12bb28c4ef9108a6c4"
+        );
+        assert_eq!(
+            hex::encode(salted_query_hash(&[0x11; 32], &[1i16, -1])),
+            "23beccd3cd7ef5ed4d7669856823ad4aa7b8606c285d39c5badb678f74b6c978"
+        );
+        assert_eq!(query_commitment(&[1i16, -1], None), query_hash(&[1i16, -1]));
+        assert_ne!(
+            query_commitment(&[1i16, -1], Some(&[0x11; 32])),
+            query_commitment(&[1i16, -1], Some(&[0x12; 32]))
+        );
+        assert_eq!(
+            hex::encode(proof_hash(&[1, 2, 3, 4, 5, 6, 7, 8], &[0xcc; 32])),
+            "91afe9348c5c5fd54b338a7fa0dfba89993a626e57bb6f8c632739694322b12d"
+        );
+    }
+}
@@ -0,0 +1,172 @@
+use std::fmt;
+
+/// Status code for a successful search, see [`VnnsError::code`] for the others.
+pub const STATUS_OK: u8 = 0;
+
+/// Errors that can occur during a search.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VnnsError {
+    /// There are no samples to search.
+    EmptySamples,
+    /// A vector has a different dimension than expected.
+    DimensionMismatch {
+        /// Index of the sample, or `None` if it is the query.
+        idx: Option<usize>,
+        expected: usize,
+        actual: usize,
+    },
+    /// A vector contains NaN or infinity.
+    NonFinite {
+        /// Index of the sample, or `None` if it is the query.
+        idx: Option<usize>,
+    },
+    /// The batch size is zero.
+    ZeroBatchSize,
+    /// The batch size is less than `2k`, so the chunks of a round can not merge the winners of at
+    /// least two chunks of the previous round, and the tournament can not make progress.
+    BatchSizeTooSmall { batch_size: usize, k: usize },
+    /// The metric id is not known.
+    UnknownMetric(u8),
+    /// The embedding matrix is malformed, e.g. its data is not a multiple of its dimension.
+    InvalidMatrix,
+    /// The radius of a range search is NaN.
+    InvalidRadius,
+    /// The filter is malformed.
+    InvalidFilter,
+    /// The metadata is malformed, or it is not given for every sample.
+    InvalidMetadata,
+    /// A sample is not within the dataset, i.e. its inclusion proof does not match the root.
+    InvalidInclusionProof {
+        /// Index of the sample.
+        idx: usize,
+    },
+    /// The proofs of the previous round are malformed, rejected, or belong to another search.
+    InvalidPreviousRound,
+    /// A sample is not the winner of the previous round at its position.
+    NotPreviousWinner {
+        /// Index of the sample.
+        idx: usize,
+    },
+    /// The samples, or the chunks of the previous round, are not consecutive within the dataset.
+    NonContiguousChunk,
+    /// The payload hash is not given for every sample.
+    InvalidPayloads,
+    /// The aggregated nodes do not form a subtree of the aggregation tree, e.g. they are not
+    /// consecutive or a node is missing proofs.
+    InvalidAggregationTree,
+    /// The quantization parameters are not supported, or a quantized value is out of their range.
+    InvalidQuantization,
+}
+
+impl VnnsError {
+    /// Status code of the error, to be committed by the program instead of aborting.
+    pub fn code(&self) -> u8 {
+        match self {
+            VnnsError::EmptySamples => 1,
+            VnnsError::DimensionMismatch { .. } => 2,
+            VnnsError::NonFinite { .. } => 3,
+            VnnsError::ZeroBatchSize => 4,
+            VnnsError::BatchSizeTooSmall { .. } => 5,
+            VnnsError::UnknownMetric(_) => 6,
+            VnnsError::InvalidMatrix => 7,
+            VnnsError::InvalidRadius => 8,
+            VnnsError::InvalidFilter => 9,
+            VnnsError::InvalidMetadata => 10,
+            VnnsError::InvalidInclusionProof { .. } => 11,
+            VnnsError::InvalidPreviousRound => 12,
+            VnnsError::NotPreviousWinner { .. } => 13,
+            VnnsError::NonContiguousChunk => 14,
+            VnnsError::InvalidPayloads => 15,
+            VnnsError::InvalidAggregationTree => 16,
+            VnnsError::InvalidQuantization => 17,
+        }
+    }
+
+    /// Short description of a status code.
+    pub fn describe_code(code: u8) -> &'static str {
+        match code {
+            STATUS_OK => "ok",
+            1 => "empty samples",
+            2 => "dimension mismatch",
+            3 => "non-finite value",
+            4 => "zero batch size",
+            5 => "batch size too small",
+            6 => "unknown metric",
+            7 => "invalid matrix",
+            8 => "invalid radius",
+            9 => "invalid filter",
+            10 => "invalid metadata",
+            11 => "invalid inclusion proof",
+            12 => "invalid previous round",
+            13 => "not a previous winner",
+            14 => "non-contiguous chunk",
+            15 => "invalid payloads",
+            16 => "invalid aggregation tree",
+            17 => "invalid quantization",
+            _ => "unknown status",
+        }
+    }
+}
+
+impl fmt::Display for VnnsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VnnsError::EmptySamples => write!(f, "no samples to search"),
+            VnnsError::DimensionMismatch {
+                idx: Some(idx),
+                expected,
+                actual,
+            } => write!(
+                f,
+                "sample {} has dimension {}, expected {}",
+                idx, actual, expected
+            ),
+            VnnsError::DimensionMismatch {
+                idx: None,
+                expected,
+                actual,
+            } => write!(f, "query has dimension {}, expected {}", actual, expected),
+            VnnsError::NonFinite { idx: Some(idx) } => {
+                write!(f, "sample {} has a non-finite value", idx)
+            }
+            VnnsError::NonFinite { idx: None } => write!(f, "query has a non-finite value"),
+            VnnsError::ZeroBatchSize => write!(f, "batch size must be positive"),
+            VnnsError::BatchSizeTooSmall { batch_size, k } => {
+                write!(
+                    f,
+                    "batch size {} must be at least twice k = {}",
+                    batch_size, k
+                )
+            }
+            VnnsError::UnknownMetric(id) => write!(f, "unknown metric id: {}", id),
+            VnnsError::InvalidMatrix => write!(f, "malformed embedding matrix"),
+            VnnsError::InvalidRadius => write!(f, "radius must not be NaN"),
+            VnnsError::InvalidFilter => write!(f, "malformed filter"),
+            VnnsError::InvalidMetadata => write!(f, "malformed or missing sample metadata"),
+            VnnsError::InvalidInclusionProof { idx } => {
+                write!(f, "sample {} is not within the dataset", idx)
+            }
+            VnnsError::InvalidPreviousRound => {
+                write!(f, "previous round does not belong to this search")
+            }
+            VnnsError::NotPreviousWinner { idx } => {
+                write!(f, "sample {} is not a winner of the previous round", idx)
+            }
+            VnnsError::NonContiguousChunk => {
+                write!(f, "samples are not consecutive within the dataset")
+            }
+            VnnsError::InvalidPayloads => write!(f, "missing sample payload hashes"),
+            VnnsError::InvalidAggregationTree => {
+                write!(
+                    f,
+                    "aggregated nodes do not form a subtree of the aggregation"
+                )
+            }
+            VnnsError::InvalidQuantization => {
+                write!(f, "unsupported or out-of-range quantization")
+            }
+        }
+    }
+}
+
+impl std::error::Error for VnnsError {}
@@ -0,0 +1,471 @@
+//! Metadata records attached to the samples, and filters over them.
+//!
+//! Both are passed to the program as bytes, see [`Filter::to_bytes`] & [`Metadata::encode_all`],
+//! so that the exact filter can be committed and the metadata can be included in the hash of each
+//! sample.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::encoding::{write_str, Reader};
+use crate::{Element, EmbeddingMatrix, VnnsError};
+
+/// Value of a metadata attribute.
+///
+/// Numbers are integers, e.g. a price is given in cents, so that the filter does not depend on
+/// floating-point comparisons.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum AttributeValue {
+    Number(i64),
+    Text(String),
+}
+
+impl AttributeValue {
+    const NUMBER_TAG: u8 = 0;
+    const TEXT_TAG: u8 = 1;
+
+    fn write_bytes(&self, bytes: &mut Vec<u8>) {
+        match self {
+            AttributeValue::Number(number) => {
+                bytes.push(Self::NUMBER_TAG);
+                bytes.extend_from_slice(&number.to_le_bytes());
+            }
+            AttributeValue::Text(text) => {
+                bytes.push(Self::TEXT_TAG);
+                write_str(bytes, text);
+            }
+        }
+    }
+
+    fn read_bytes(reader: &mut Reader) -> Option<Self> {
+        match reader.read_u8()? {
+            Self::NUMBER_TAG => Some(AttributeValue::Number(reader.read_i64()?)),
+            Self::TEXT_TAG => Some(AttributeValue::Text(reader.read_str()?)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for AttributeValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AttributeValue::Number(number) => write!(f, "{}", number),
+            AttributeValue::Text(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+/// Attributes of a sample, such as its category, price or region.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Metadata(pub BTreeMap<String, AttributeValue>);
+
+impl Metadata {
+    /// Value of the given attribute, if there is one.
+    pub fn get(&self, field: &str) -> Option<&AttributeValue> {
+        self.0.get(field)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Byte representation of the metadata, to be included in the hash of its sample.
+    ///
+    /// ( len as u32 || field[0] || value[0] || ... ) in field order.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.write_bytes(&mut bytes);
+        bytes
+    }
+
+    fn write_bytes(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&(self.0.len() as u32).to_le_bytes());
+        for (field, value) in &self.0 {
+            write_str(bytes, field);
+            value.write_bytes(bytes);
+        }
+    }
+
+    fn read_bytes(reader: &mut Reader) -> Option<Self> {
+        let len = reader.read_u32()?;
+        let mut attributes = BTreeMap::new();
+        for _ in 0..len {
+            let field = reader.read_str()?;
+            let value = AttributeValue::read_bytes(reader)?;
+            attributes.insert(field, value);
+        }
+        Some(Self(attributes))
+    }
+
+    /// Encodes the metadata of all samples to be read with a single `read_vec` within the program.
+    ///
+    /// ( len as u32 || metadata[0] || metadata[1] || ... ), see [`Metadata::to_bytes`].
+    pub fn encode_all(metadata: &[Metadata]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&(metadata.len() as u32).to_le_bytes());
+        for metadata in metadata {
+            metadata.write_bytes(&mut bytes);
+        }
+        bytes
+    }
+
+    /// Decodes the metadata encoded with [`Metadata::encode_all`].
+    pub fn decode_all(bytes: &[u8]) -> Result<Vec<Metadata>, VnnsError> {
+        let mut reader = Reader::new(bytes);
+        let metadata = reader
+            .read_u32()
+            .and_then(|len| {
+                (0..len)
+                    .map(|_| Metadata::read_bytes(&mut reader))
+                    .collect()
+            })
+            .ok_or(VnnsError::InvalidMetadata)?;
+        if !reader.is_empty() {
+            return Err(VnnsError::InvalidMetadata);
+        }
+
+        Ok(metadata)
+    }
+}
+
+/// A condition on a single attribute of the metadata.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Condition {
+    /// The attribute equals the value.
+    Equals {
+        field: String,
+        value: AttributeValue,
+    },
+    /// The attribute is a number within `[min, max]`.
+    Range { field: String, min: i64, max: i64 },
+}
+
+impl Condition {
+    const EQUALS_TAG: u8 = 0;
+    const RANGE_TAG: u8 = 1;
+
+    /// Whether the metadata satisfies the condition, a missing attribute never does.
+    pub fn matches(&self, metadata: &Metadata) -> bool {
+        match self {
+            Condition::Equals { field, value } => metadata.get(field) == Some(value),
+            Condition::Range { field, min, max } => match metadata.get(field) {
+                Some(AttributeValue::Number(number)) => min <= number && number <= max,
+                _ => false,
+            },
+        }
+    }
+
+    fn write_bytes(&self, bytes: &mut Vec<u8>) {
+        match self {
+            Condition::Equals { field, value } => {
+                bytes.push(Self::EQUALS_TAG);
+                write_str(bytes, field);
+                value.write_bytes(bytes);
+            }
+            Condition::Range { field, min, max } => {
+                bytes.push(Self::RANGE_TAG);
+                write_str(bytes, field);
+                bytes.extend_from_slice(&min.to_le_bytes());
+                bytes.extend_from_slice(&max.to_le_bytes());
+            }
+        }
+    }
+
+    fn read_bytes(reader: &mut Reader) -> Option<Self> {
+        match reader.read_u8()? {
+            Self::EQUALS_TAG => Some(Condition::Equals {
+                field: reader.read_str()?,
+                value: AttributeValue::read_bytes(reader)?,
+            }),
+            Self::RANGE_TAG => Some(Condition::Range {
+                field: reader.read_str()?,
+                min: reader.read_i64()?,
+                max: reader.read_i64()?,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Parses `field=value` for equality, where the value is a number if it can be parsed as one, and
+/// `field=min..max` for an inclusive numeric range where either bound can be omitted.
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (field, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected field=value, got: {}", s))?;
+        let field = field.to_string();
+
+        if let Some((min, max)) = value.split_once("..") {
+            let parse_bound = |bound: &str, default: i64| {
+                if bound.is_empty() {
+                    Ok(default)
+                } else {
+                    bound
+                        .parse::<i64>()
+                        .map_err(|_| format!("invalid range bound: {}", bound))
+                }
+            };
+            return Ok(Condition::Range {
+                field,
+                min: parse_bound(min, i64::MIN)?,
+                max: parse_bound(max, i64::MAX)?,
+            });
+        }
+
+        let value = match value.parse::<i64>() {
+            Ok(number) => AttributeValue::Number(number),
+            Err(_) => AttributeValue::Text(value.to_string()),
+        };
+        Ok(Condition::Equals { field, value })
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Condition::Equals { field, value } => write!(f, "{}={}", field, value),
+            Condition::Range { field, min, max } => write!(f, "{}={}..{}", field, min, max),
+        }
+    }
+}
+
+/// A conjunction of conditions, i.e. a sample passes the filter if it satisfies all of them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Filter {
+    pub conditions: Vec<Condition>,
+}
+
+impl Filter {
+    pub fn new(conditions: Vec<Condition>) -> Self {
+        Self { conditions }
+    }
+
+    /// Whether the metadata satisfies all conditions.
+    pub fn matches(&self, metadata: &Metadata) -> bool {
+        self.conditions
+            .iter()
+            .all(|condition| condition.matches(metadata))
+    }
+
+    /// Byte representation of the filter, to be read & committed by the program.
+    ///
+    /// ( len as u32 || condition[0] || condition[1] || ... )
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&(self.conditions.len() as u32).to_le_bytes());
+        for condition in &self.conditions {
+            condition.write_bytes(&mut bytes);
+        }
+        bytes
+    }
+
+    /// Decodes a filter encoded with [`Filter::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, VnnsError> {
+        let mut reader = Reader::new(bytes);
+        let conditions = reader
+            .read_u32()
+            .and_then(|len| {
+                (0..len)
+                    .map(|_| Condition::read_bytes(&mut reader))
+                    .collect()
+            })
+            .ok_or(VnnsError::InvalidFilter)?;
+        if !reader.is_empty() {
+            return Err(VnnsError::InvalidFilter);
+        }
+
+        Ok(Self { conditions })
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, condition) in self.conditions.iter().enumerate() {
+            if i > 0 {
+                write!(f, " & ")?;
+            }
+            write!(f, "{}", condition)?;
+        }
+        Ok(())
+    }
+}
+
+/// Results of a search, i.e. indices of the samples, possibly along with their scores.
+pub trait SearchResults {
+    /// Results of a search without samples.
+    fn empty() -> Self;
+
+    /// Maps the index of each result, keeping everything else as-is.
+    fn map_indices(self, f: impl FnMut(usize) -> usize) -> Self;
+}
+
+impl SearchResults for Vec<usize> {
+    fn empty() -> Self {
+        Vec::new()
+    }
+
+    fn map_indices(self, f: impl FnMut(usize) -> usize) -> Self {
+        self.into_iter().map(f).collect()
+    }
+}
+
+/// Searches only the samples whose metadata passes the filter, and maps the results of the given
+/// search back to indices within all samples.
+///
+/// The metadata must be given for every sample. If no sample passes the filter, the result is
+/// empty without searching.
+pub fn search_filtered<T, R, F>(
+    samples: &EmbeddingMatrix<T>,
+    metadata: &[Metadata],
+    filter: &Filter,
+    search: F,
+) -> Result<R, VnnsError>
+where
+    T: Element,
+    R: SearchResults,
+    F: FnOnce(&EmbeddingMatrix<T>) -> Result<R, VnnsError>,
+{
+    if samples.is_empty() {
+        return Err(VnnsError::EmptySamples);
+    }
+    if metadata.len() != samples.len() {
+        return Err(VnnsError::InvalidMetadata);
+    }
+
+    let passed = (0..samples.len())
+        .filter(|&idx| filter.matches(&metadata[idx]))
+        .collect::<Vec<_>>();
+    if passed.is_empty() {
+        return Ok(R::empty());
+    }
+
+    let results = search(&samples.select(&passed))?;
+    Ok(results.map_indices(|idx| passed[idx]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(category: &str, price: i64) -> Metadata {
+        Metadata(BTreeMap::from([
+            (
+                "category".to_string(),
+                AttributeValue::Text(category.to_string()),
+            ),
+            ("price".to_string(), AttributeValue::Number(price)),
+        ]))
+    }
+
+    #[test]
+    fn test_filter() {
+        let filter = Filter::new(vec![
+            "category=fruit".parse().unwrap(),
+            "price=100..".parse().unwrap(),
+        ]);
+        assert!(filter.matches(&metadata("fruit", 100)));
+        assert!(!filter.matches(&metadata("fruit", 99)));
+        assert!(!filter.matches(&metadata("vegetable", 500)));
+        assert!(!filter.matches(&Metadata::default()));
+        assert!(Filter::default().matches(&Metadata::default()));
+
+        // numbers are parsed as such, and never equal to text
+        assert_eq!(
+            "price=250".parse::<Condition>().unwrap(),
+            Condition::Equals {
+                field: "price".to_string(),
+                value: AttributeValue::Number(250)
+            }
+        );
+        assert!(!Filter::new(vec!["price=ab".parse().unwrap()]).matches(&metadata("ab", 1)));
+        assert!("price".parse::<Condition>().is_err());
+        assert!("price=1..x".parse::<Condition>().is_err());
+    }
+
+    #[test]
+    fn test_filter_bytes() {
+        let filter = Filter::new(vec![
+            "region=EU".parse().unwrap(),
+            "price=..500".parse().unwrap(),
+        ]);
+        let bytes = filter.to_bytes();
+        assert_eq!(Filter::from_bytes(&bytes), Ok(filter));
+        assert_eq!(
+            Filter::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(VnnsError::InvalidFilter)
+        );
+        assert_eq!(
+            Filter::from_bytes(&[bytes.as_slice(), &[0]].concat()),
+            Err(VnnsError::InvalidFilter)
+        );
+
+        let metadata = vec![metadata("fruit", 100), Metadata::default()];
+        let bytes = Metadata::encode_all(&metadata);
+        assert_eq!(Metadata::decode_all(&bytes), Ok(metadata));
+        assert_eq!(
+            Metadata::decode_all(&bytes[1..]),
+            Err(VnnsError::InvalidMetadata)
+        );
+    }
+
+    #[test]
+    fn test_metadata_json() {
+        let json = r#"{"category":"fruit","price":100}"#;
+        let parsed = serde_json::from_str::<Metadata>(json).unwrap();
+        assert_eq!(parsed, metadata("fruit", 100));
+        assert_eq!(serde_json::to_string(&parsed).unwrap(), json);
+    }
+
+    #[test]
+    fn test_search_filtered() {
+        let samples = EmbeddingMatrix::from_rows(&[[0.0], [1.0], [2.0], [3.0]]).unwrap();
+        let metadata = vec![
+            metadata("fruit", 100),
+            metadata("vegetable", 100),
+            metadata("fruit", 300),
+            metadata("vegetable", 300),
+        ];
+        let top_k =
+            |samples: &EmbeddingMatrix| crate::compute_top_k(samples, &[0.0], 2, crate::Metric::L2);
+
+        let filter = Filter::new(vec!["category=vegetable".parse().unwrap()]);
+        assert_eq!(
+            search_filtered(&samples, &metadata, &filter, top_k),
+            Ok(vec![1, 3])
+        );
+
+        let filter = Filter::new(vec!["price=200..".parse().unwrap()]);
+        assert_eq!(
+            search_filtered(&samples, &metadata, &filter, top_k),
+            Ok(vec![2, 3])
+        );
+
+        let filter = Filter::new(vec!["category=meat".parse().unwrap()]);
+        assert_eq!(
+            search_filtered(&samples, &metadata, &filter, top_k),
+            Ok(vec![])
+        );
+        assert_eq!(
+            search_filtered(&samples, &metadata[1..], &filter, top_k),
+            Err(VnnsError::InvalidMetadata)
+        );
+
+        // the scores are kept, and the runner-up is among the samples that pass the filter
+        let filter = Filter::new(vec!["category=vegetable".parse().unwrap()]);
+        let scored = search_filtered(&samples, &metadata, &filter, |samples: &EmbeddingMatrix| {
+            crate::compute_top_k_scored(samples, &[0.0], 1, crate::Metric::L2)
+        })
+        .unwrap();
+        assert_eq!(scored.idxs, vec![1]);
+        assert_eq!(scored.scores, vec![1 << crate::SCORE_FRACTION_BITS]);
+        assert_eq!(scored.runner_up, Some(3 << crate::SCORE_FRACTION_BITS));
+    }
+}
@@ -1,46 +1,213 @@
+mod aggregate;
+pub use aggregate::{
+    aggregate_outputs, aggregate_root, decode_nodes, merge_nodes, proof_nodes, proofs_tree,
+    search_digest, AggregatedOutputs, AggregationNode,
+};
+
+mod chain;
+pub use chain::{link_to_dataset, link_to_previous_round, LinkedChunk};
+
+pub mod encoding;
+
+mod error;
+pub use error::{VnnsError, STATUS_OK};
+
+mod filter;
+pub use filter::{search_filtered, AttributeValue, Condition, Filter, Metadata, SearchResults};
+
+mod matrix;
+pub use matrix::{Element, EmbeddingMatrix};
+
+mod merkle;
+pub use merkle::{empty_root, subtree_root, verify_inclusion, Hash, MerkleProof, MerkleTree};
+
+mod metric;
+pub use metric::{Metric, COSINE_FRACTION_BITS};
+
+mod mode;
+pub use mode::SearchMode;
+
+mod ordering;
+use ordering::top_k_indices;
+pub use ordering::{cmp_ranked, Distance};
+
+mod outputs;
+pub use outputs::{DecodeError, PublicOutputs};
+
+mod quantized;
+pub use quantized::{
+    compute_top_k_quantized, compute_top_k_quantized_scored, range_search_quantized,
+    range_search_quantized_scored, recall, Quantization,
+};
+
+mod score;
+pub use score::{score_to_distance, Score, Scored, ToScore, SCORE_FRACTION_BITS};
+
+mod tournament;
+pub use tournament::{Contestant, Tournament, TournamentStep};
+
 /// Compute the best sample from a list of samples given a query.
 ///
-/// Uses the dot product to compute the similarity between the samples and the query.
-/// Assumes that the samples and the query have the same length, and the input values are
-/// scale-invariant and within the range [-1, 1].
-pub fn compute_best_sample(samples: &[Vec<f32>], query: &[f32]) -> usize {
-    samples
-        .iter()
-        .map(|sample| {
-            sample
-                .iter()
-                .zip(query)
-                .map(|(a, b)| (a - b).powi(2))
-                .sum::<f32>()
-                .sqrt()
-        })
+/// Uses the given [`Metric`] to compute the distance between the samples and the query, and returns
+/// the index of the sample with the smallest distance. Ties & NaN distances are resolved as
+/// described in [`cmp_ranked`], i.e. the lowest index wins among equal distances.
+///
+/// Returns an error if there are no samples, if a sample has a different dimension than the query,
+/// or if there is a non-finite value within the vectors.
+pub fn compute_best_sample(
+    samples: &EmbeddingMatrix,
+    query: &[f32],
+    metric: Metric,
+) -> Result<usize, VnnsError> {
+    validate_finite(samples, query)?;
+
+    let idx = samples
+        .rows()
+        .map(|sample| metric.distance(sample, query))
         .enumerate()
-        .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
-        .unwrap()
-        .0
+        .min_by(|a, b| cmp_ranked(*a, *b))
+        .map(|(idx, _)| idx)
+        .expect("samples are not empty");
+
+    Ok(idx)
+}
+
+/// Compute the `k` best samples from a list of samples given a query.
+///
+/// Returns the indices of the samples in order, starting with the closest one. If there are less
+/// than `k` samples, all of them are returned. Fails for the same inputs as
+/// [`compute_best_sample`].
+pub fn compute_top_k(
+    samples: &EmbeddingMatrix,
+    query: &[f32],
+    k: usize,
+    metric: Metric,
+) -> Result<Vec<usize>, VnnsError> {
+    compute_top_k_scored(samples, query, k, metric).map(|scored| scored.idxs)
+}
+
+/// Same as [`compute_top_k`], but also returns the score of each result & of the runner-up.
+pub fn compute_top_k_scored(
+    samples: &EmbeddingMatrix,
+    query: &[f32],
+    k: usize,
+    metric: Metric,
+) -> Result<Scored, VnnsError> {
+    validate_finite(samples, query)?;
+
+    let distances = samples
+        .rows()
+        .map(|sample| metric.distance(sample, query))
+        .collect::<Vec<_>>();
+
+    Ok(Scored::new(&distances, top_k_indices(&distances, k)))
+}
+
+/// Compute all samples within distance `radius` of the query, including the boundary.
+///
+/// Returns the indices of the matching samples in dataset order, which may be empty. Samples with a
+/// NaN distance never match. Fails for the same inputs as [`compute_best_sample`], and for a NaN
+/// radius.
+pub fn range_search(
+    samples: &EmbeddingMatrix,
+    query: &[f32],
+    radius: f32,
+    metric: Metric,
+) -> Result<Vec<usize>, VnnsError> {
+    range_search_scored(samples, query, radius, metric).map(|scored| scored.idxs)
+}
+
+/// Same as [`range_search`], but also returns the score of each result & of the runner-up, i.e. the
+/// closest sample outside the radius.
+pub fn range_search_scored(
+    samples: &EmbeddingMatrix,
+    query: &[f32],
+    radius: f32,
+    metric: Metric,
+) -> Result<Scored, VnnsError> {
+    if radius.is_nan() {
+        return Err(VnnsError::InvalidRadius);
+    }
+    validate_finite(samples, query)?;
+
+    let distances = samples
+        .rows()
+        .map(|sample| metric.distance(sample, query))
+        .collect::<Vec<_>>();
+    let idxs = (0..distances.len())
+        .filter(|&idx| distances[idx] <= radius)
+        .collect();
+
+    Ok(Scored::new(&distances, idxs))
 }
 
+/// Checks that there are samples, and that the samples have the same dimension as the query.
+fn validate_dimensions<T: Element>(
+    samples: &EmbeddingMatrix<T>,
+    query: &[T],
+) -> Result<(), VnnsError> {
+    if samples.is_empty() {
+        return Err(VnnsError::EmptySamples);
+    }
+    if samples.dim() != query.len() {
+        return Err(VnnsError::DimensionMismatch {
+            idx: None,
+            expected: samples.dim(),
+            actual: query.len(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Checks the dimensions, and that all values within the samples & the query are finite.
+fn validate_finite(samples: &EmbeddingMatrix, query: &[f32]) -> Result<(), VnnsError> {
+    validate_dimensions(samples, query)?;
+
+    if !query.iter().all(|value| value.is_finite()) {
+        return Err(VnnsError::NonFinite { idx: None });
+    }
+    match samples
+        .rows()
+        .position(|sample| !sample.iter().all(|value| value.is_finite()))
+    {
+        Some(idx) => Err(VnnsError::NonFinite { idx: Some(idx) }),
+        None => Ok(()),
+    }
+}
+
+/// Searches the `k` best samples in a tournament, where the samples are processed in chunks of
+/// `batch_size` and the `k` best samples of each chunk move on to the next round. The batch size
+/// must be at least `2k`, see [`Tournament`].
+///
+/// Returns the winners in order, starting with the closest one. Each winner has its index within
+/// the given samples, along with the path of rounds & chunks that led to it.
 pub fn iterative_similarity_search(
-    samples: Vec<Vec<f32>>,
-    query: Vec<f32>,
+    samples: &EmbeddingMatrix,
+    query: &[f32],
     batch_size: usize,
-) -> (usize, Vec<f32>) {
-    let mut current_samples = samples;
-    while current_samples.len() > batch_size {
-        let mut best_samples = Vec::new();
-        for chunk in current_samples.chunks(batch_size) {
-            best_samples.push(compute_best_sample(chunk, &query));
-        }
-        current_samples = best_samples
-            .iter()
-            .map(|&idx| current_samples[idx].clone())
-            .collect::<Vec<_>>();
-    }
-
-    let idx = compute_best_sample(&current_samples, &query);
-    let result = current_samples[idx].clone();
-
-    (idx, result)
+    k: usize,
+    metric: Metric,
+) -> Result<Vec<Contestant>, VnnsError> {
+    if batch_size == 0 {
+        return Err(VnnsError::ZeroBatchSize);
+    }
+    if batch_size < 2 * k {
+        return Err(VnnsError::BatchSizeTooSmall { batch_size, k });
+    }
+    validate_finite(samples, query)?;
+
+    let mut tournament = Tournament::new(samples.len(), batch_size);
+    while !tournament.is_finished() {
+        let winners = tournament
+            .chunks()
+            .into_iter()
+            .map(|chunk| compute_top_k(&samples.select(&chunk), query, k, metric))
+            .collect::<Result<Vec<_>, _>>()?;
+        tournament.advance(&winners);
+    }
+
+    Ok(tournament.into_winners())
 }
 
 #[cfg(test)]
@@ -49,6 +216,10 @@ mod tests {
 
     use super::*;
 
+    fn matrix(rows: &[Vec<f32>]) -> EmbeddingMatrix {
+        EmbeddingMatrix::from_rows(rows).unwrap()
+    }
+
     #[test]
     fn test_compute_best_sample() {
         let samples = vec![
@@ -57,7 +228,14 @@ mod tests {
             vec![0.700, 0.800, 0.900],
         ];
         let query = vec![0.1, 0.2, 0.3];
-        assert_eq!(compute_best_sample(&samples, &query), 0);
+        assert_eq!(
+            compute_best_sample(&matrix(&samples), &query, Metric::L2),
+            Ok(0)
+        );
+        assert_eq!(
+            compute_best_sample(&matrix(&samples), &query, Metric::SquaredL2),
+            Ok(0)
+        );
     }
 
     #[test]
@@ -71,9 +249,200 @@ mod tests {
             vec![0.16, 0.17, 0.18],
         ];
         let query = vec![0.99, 0.99, 0.99];
+        let result =
+            iterative_similarity_search(&matrix(&samples), &query, 2, 1, Metric::L2).unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(samples[result[0].idx], vec![0.7, 0.8, 0.9]);
+        assert_eq!(result[0].path.len(), 3);
+        assert_eq!(result[0].path[0].chunk, 1);
+    }
+
+    #[test]
+    fn test_compute_top_k() {
+        let samples = vec![
+            vec![0.5, 0.5],
+            vec![0.1, 0.1],
+            vec![0.9, 0.9],
+            vec![0.3, 0.3],
+        ];
+        let query = vec![0.0, 0.0];
+        assert_eq!(
+            compute_top_k(&matrix(&samples), &query, 3, Metric::L2),
+            Ok(vec![1, 3, 0])
+        );
+        assert_eq!(
+            compute_top_k(&matrix(&samples), &query, 10, Metric::L2),
+            Ok(vec![1, 3, 0, 2])
+        );
+        assert_eq!(
+            compute_top_k(&matrix(&samples), &query, 1, Metric::L2).unwrap()[0],
+            compute_best_sample(&matrix(&samples), &query, Metric::L2).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_iterative_top_k_search() {
+        let samples = (0..20).map(|i| vec![i as f32, 0.0]).collect::<Vec<_>>();
+        let query = vec![11.2, 0.0];
+        let result =
+            iterative_similarity_search(&matrix(&samples), &query, 6, 3, Metric::SquaredL2)
+                .unwrap();
+        let idxs = result.into_iter().map(|c| c.idx).collect::<Vec<_>>();
+        assert_eq!(idxs, vec![11, 12, 10]);
+    }
+
+    #[test]
+    fn test_compute_best_sample_metrics() {
+        // the longest vector wins under inner product, but not under cosine
+        let samples = vec![vec![1.0, 1.0], vec![10.0, 0.0], vec![0.5, 0.6]];
+        let query = vec![1.0, 1.0];
+        assert_eq!(
+            compute_best_sample(&matrix(&samples), &query, Metric::InnerProduct),
+            Ok(1)
+        );
+        assert_eq!(
+            compute_best_sample(&matrix(&samples), &query, Metric::Cosine),
+            Ok(0)
+        );
+        assert_eq!(
+            compute_best_sample(&matrix(&samples), &query, Metric::Manhattan),
+            Ok(0)
+        );
+    }
+
+    #[test]
+    fn test_tie_breaking() {
+        // identical vectors, the lowest index wins
+        let samples = vec![
+            vec![0.9, 0.9],
+            vec![0.5, 0.5],
+            vec![0.1, 0.1],
+            vec![0.5, 0.5],
+        ];
+        let query = vec![0.5, 0.5];
+        assert_eq!(
+            compute_best_sample(&matrix(&samples), &query, Metric::L2),
+            Ok(1)
+        );
+        assert_eq!(
+            compute_top_k(&matrix(&samples), &query, 2, Metric::L2),
+            Ok(vec![1, 3])
+        );
+
+        // the same holds across the tournament
+        let samples = (0..12).map(|i| vec![(i % 3) as f32]).collect::<Vec<_>>();
+        let result =
+            iterative_similarity_search(&matrix(&samples), &[2.0], 6, 3, Metric::L2).unwrap();
+        let idxs = result.into_iter().map(|c| c.idx).collect::<Vec<_>>();
+        assert_eq!(idxs, vec![2, 5, 8]);
+
+        // -0.0 & +0.0 are equal under inner product with a zero query
+        let samples = vec![vec![-1.0], vec![1.0]];
+        assert_eq!(
+            compute_best_sample(&matrix(&samples), &[0.0], Metric::InnerProduct),
+            Ok(0)
+        );
+
+        // overflowing cosine distances are NaN, and NaN never wins
+        let samples = vec![vec![1e30, 1e30], vec![-1.0, 1.0]];
+        assert_eq!(
+            compute_best_sample(&matrix(&samples), &[1e30, 1e30], Metric::Cosine),
+            Ok(1)
+        );
+    }
+
+    #[test]
+    fn test_range_search() {
+        let samples = vec![
+            vec![0.0, 0.0],
+            vec![3.0, 4.0],
+            vec![1.0, 0.0],
+            vec![0.0, 6.0],
+            vec![0.0, 1.0],
+        ];
+        let query = vec![0.0, 0.0];
+        assert_eq!(
+            range_search(&matrix(&samples), &query, 1.0, Metric::L2),
+            Ok(vec![0, 2, 4])
+        );
+        assert_eq!(
+            range_search(&matrix(&samples), &query, 5.0, Metric::L2),
+            Ok(vec![0, 1, 2, 4])
+        );
+        assert_eq!(
+            range_search(&matrix(&samples), &query, -1.0, Metric::L2),
+            Ok(vec![])
+        );
+        assert_eq!(
+            range_search(&matrix(&samples), &query, f32::NAN, Metric::L2),
+            Err(VnnsError::InvalidRadius)
+        );
+
+        // NaN distances are never within the radius
+        let samples = vec![vec![1e30, 1e30], vec![-1.0, 1.0]];
+        assert_eq!(
+            range_search(
+                &matrix(&samples),
+                &[1e30, 1e30],
+                f32::INFINITY,
+                Metric::Cosine
+            ),
+            Ok(vec![1])
+        );
+    }
+
+    #[test]
+    fn test_scored_search() {
+        let samples = (0..5).map(|i| vec![i as f32]).collect::<Vec<_>>();
+        let query = vec![1.25];
+        let scored = compute_top_k_scored(&matrix(&samples), &query, 2, Metric::L2).unwrap();
+        assert_eq!(scored.idxs, vec![1, 2]);
+        assert_eq!(scored.scores, vec![0.25f32.to_score(), 0.75f32.to_score()]);
+        assert_eq!(scored.runner_up, Some(1.25f32.to_score()));
+        assert_eq!(scored.margin(), Some(0.5f32.to_score()));
+
+        let scored = range_search_scored(&matrix(&samples), &query, 1.0, Metric::L2).unwrap();
+        assert_eq!(scored.idxs, vec![1, 2]);
+        assert_eq!(scored.runner_up, Some(1.25f32.to_score()));
+    }
+
+    #[test]
+    fn test_invalid_inputs() {
+        let query = vec![0.5, 0.5];
+        assert_eq!(
+            compute_best_sample(&matrix(&[]), &query, Metric::L2),
+            Err(VnnsError::EmptySamples)
+        );
+        assert_eq!(
+            compute_top_k(&matrix(&[vec![0.1, 0.2, 0.3]]), &query, 1, Metric::L2),
+            Err(VnnsError::DimensionMismatch {
+                idx: None,
+                expected: 3,
+                actual: 2
+            })
+        );
+        assert_eq!(
+            compute_best_sample(&matrix(&[vec![0.1, f32::NAN]]), &query, Metric::L2),
+            Err(VnnsError::NonFinite { idx: Some(0) })
+        );
+        assert_eq!(
+            compute_best_sample(
+                &matrix(&[vec![0.1, 0.2]]),
+                &[f32::INFINITY, 0.0],
+                Metric::L2
+            ),
+            Err(VnnsError::NonFinite { idx: None })
+        );
+        assert_eq!(
+            iterative_similarity_search(&matrix(&[vec![0.1, 0.2]]), &query, 0, 1, Metric::L2),
+            Err(VnnsError::ZeroBatchSize)
+        );
         assert_eq!(
-            iterative_similarity_search(samples, query, 2).1,
-            vec![0.4, 0.5, 0.6]
+            iterative_similarity_search(&matrix(&[vec![0.1, 0.2]]), &query, 3, 2, Metric::L2),
+            Err(VnnsError::BatchSizeTooSmall {
+                batch_size: 3,
+                k: 2
+            })
         );
     }
 }
@@ -0,0 +1,203 @@
+use serde::{Deserialize, Serialize};
+
+use crate::VnnsError;
+
+/// A value that can be stored within an [`EmbeddingMatrix`].
+pub trait Element: Copy {
+    /// Number of bytes of a single value.
+    const SIZE: usize;
+
+    /// Appends the little-endian bytes of the value.
+    fn write_le_bytes(&self, bytes: &mut Vec<u8>);
+
+    /// Reads a value from exactly [`Element::SIZE`] little-endian bytes.
+    fn read_le_bytes(bytes: &[u8]) -> Self;
+}
+
+impl Element for f32 {
+    const SIZE: usize = 4;
+
+    fn write_le_bytes(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.to_le_bytes());
+    }
+
+    fn read_le_bytes(bytes: &[u8]) -> Self {
+        f32::from_le_bytes(bytes.try_into().expect("expected 4 bytes"))
+    }
+}
+
+impl Element for i16 {
+    const SIZE: usize = 2;
+
+    fn write_le_bytes(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.to_le_bytes());
+    }
+
+    fn read_le_bytes(bytes: &[u8]) -> Self {
+        i16::from_le_bytes(bytes.try_into().expect("expected 2 bytes"))
+    }
+}
+
+/// Embedding vectors of the same dimension, stored contiguously in row-major order.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EmbeddingMatrix<T = f32> {
+    dim: usize,
+    data: Vec<T>,
+}
+
+impl<T: Element> EmbeddingMatrix<T> {
+    /// Creates a matrix from its row-major data, which must be a multiple of the dimension.
+    pub fn new(dim: usize, data: Vec<T>) -> Result<Self, VnnsError> {
+        if dim == 0 || data.len() % dim != 0 {
+            return Err(VnnsError::InvalidMatrix);
+        }
+
+        Ok(Self { dim, data })
+    }
+
+    /// Creates a matrix from its rows, which must have the same dimension.
+    ///
+    /// An empty list of rows results in an empty matrix of dimension 1.
+    pub fn from_rows<R: AsRef<[T]>>(rows: &[R]) -> Result<Self, VnnsError> {
+        let dim = rows.first().map(|row| row.as_ref().len()).unwrap_or(1);
+
+        let mut data = Vec::with_capacity(rows.len() * dim);
+        for (idx, row) in rows.iter().enumerate() {
+            let row = row.as_ref();
+            if row.len() != dim {
+                return Err(VnnsError::DimensionMismatch {
+                    idx: Some(idx),
+                    expected: dim,
+                    actual: row.len(),
+                });
+            }
+            data.extend_from_slice(row);
+        }
+
+        Self::new(dim, data)
+    }
+
+    /// Dimension of each row.
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Number of rows.
+    pub fn len(&self) -> usize {
+        self.data.len() / self.dim
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Row at the given index.
+    pub fn row(&self, idx: usize) -> &[T] {
+        &self.data[idx * self.dim..(idx + 1) * self.dim]
+    }
+
+    /// Iterates over the rows.
+    pub fn rows(&self) -> std::slice::ChunksExact<'_, T> {
+        self.data.chunks_exact(self.dim)
+    }
+
+    /// All values, in row-major order.
+    pub fn as_slice(&self) -> &[T] {
+        &self.data
+    }
+
+    /// Creates a new matrix with the rows at the given indices, in the given order.
+    pub fn select(&self, idxs: &[usize]) -> Self {
+        let mut data = Vec::with_capacity(idxs.len() * self.dim);
+        for &idx in idxs {
+            data.extend_from_slice(self.row(idx));
+        }
+
+        Self {
+            dim: self.dim,
+            data,
+        }
+    }
+
+    /// Little-endian bytes of the row at the given index.
+    pub fn row_bytes(&self, idx: usize) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.dim * T::SIZE);
+        for value in self.row(idx) {
+            value.write_le_bytes(&mut bytes);
+        }
+        bytes
+    }
+
+    /// Encodes the matrix to be read with a single `read_vec` within the program.
+    ///
+    /// ( dim as u32 || data[0] || data[1] || ... )
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(4 + self.data.len() * T::SIZE);
+        bytes.extend_from_slice(&(self.dim as u32).to_le_bytes());
+        for value in &self.data {
+            value.write_le_bytes(&mut bytes);
+        }
+        bytes
+    }
+
+    /// Decodes a matrix encoded with [`EmbeddingMatrix::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, VnnsError> {
+        if bytes.len() < 4 || (bytes.len() - 4) % T::SIZE != 0 {
+            return Err(VnnsError::InvalidMatrix);
+        }
+
+        let dim = u32::from_le_bytes(bytes[..4].try_into().expect("expected 4 bytes"));
+        let data = bytes[4..]
+            .chunks_exact(T::SIZE)
+            .map(T::read_le_bytes)
+            .collect();
+
+        Self::new(dim as usize, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matrix() {
+        let matrix = EmbeddingMatrix::from_rows(&[[1.0, 2.0], [3.0, 4.0], [5.0, 6.0]]).unwrap();
+        assert_eq!(matrix.dim(), 2);
+        assert_eq!(matrix.len(), 3);
+        assert_eq!(matrix.row(1), &[3.0, 4.0]);
+        assert_eq!(matrix.rows().count(), 3);
+
+        let selected = matrix.select(&[2, 0]);
+        assert_eq!(selected.as_slice(), &[5.0, 6.0, 1.0, 2.0]);
+        assert_eq!(
+            selected.row_bytes(1),
+            [1.0f32.to_le_bytes(), 2.0f32.to_le_bytes()].concat()
+        );
+    }
+
+    #[test]
+    fn test_matrix_bytes() {
+        let matrix = EmbeddingMatrix::from_rows(&[vec![1i16, -2, 3], vec![4, 5, -6]]).unwrap();
+        let bytes = matrix.to_bytes();
+        assert_eq!(bytes.len(), 4 + 6 * 2);
+        assert_eq!(EmbeddingMatrix::from_bytes(&bytes), Ok(matrix));
+
+        // truncated data, and a dimension that does not divide the data
+        assert!(EmbeddingMatrix::<i16>::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(EmbeddingMatrix::<i16>::from_bytes(&[4, 0, 0, 0, 1, 0, 2, 0]).is_err());
+        assert!(EmbeddingMatrix::<f32>::from_bytes(&[0, 0]).is_err());
+    }
+
+    #[test]
+    fn test_matrix_mismatch() {
+        assert_eq!(
+            EmbeddingMatrix::from_rows(&[vec![1.0, 2.0], vec![3.0]]),
+            Err(VnnsError::DimensionMismatch {
+                idx: Some(1),
+                expected: 2,
+                actual: 1
+            })
+        );
+    }
+}
@@ -0,0 +1,362 @@
+//! Merkle tree over the hashes of the samples within a dataset.
+//!
+//! The leaves are padded with zero hashes up to a power of two, so that every inclusion proof has
+//! the same length and the index of a leaf is determined by its proof alone. The root commits to
+//! the number of leaves as well, so that the size of the dataset is bound to its root, and a leaf
+//! can only be proven at an index below that size.
+
+use serde::{Deserialize, Serialize};
+
+use crate::encoding::{item_hash, node_hash, root_hash};
+use crate::{Element, EmbeddingMatrix, Metadata, Quantization, VnnsError};
+
+/// A SHA-256 digest.
+pub type Hash = [u8; 32];
+
+/// A Merkle tree, where each level is kept to create inclusion proofs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MerkleTree {
+    num_leaves: usize,
+    /// Levels from the padded leaves up to the root.
+    levels: Vec<Vec<Hash>>,
+}
+
+impl MerkleTree {
+    pub fn new(leaves: Vec<Hash>) -> Self {
+        let num_leaves = leaves.len();
+        let mut level = leaves;
+        level.resize(num_leaves.next_power_of_two(), [0u8; 32]);
+
+        let mut levels = vec![level];
+        while levels[levels.len() - 1].len() > 1 {
+            let next = levels[levels.len() - 1]
+                .chunks_exact(2)
+                .map(|pair| node_hash(&pair[0], &pair[1]))
+                .collect();
+            levels.push(next);
+        }
+
+        Self { num_leaves, levels }
+    }
+
+    /// Number of leaves, without the padding.
+    pub fn num_leaves(&self) -> usize {
+        self.num_leaves
+    }
+
+    /// Root of the tree, which commits to the number of leaves, see [`root_hash`].
+    pub fn root(&self) -> Hash {
+        root_hash(&self.levels[self.levels.len() - 1][0], self.num_leaves)
+    }
+
+    /// Leaf at the given index.
+    pub fn leaf(&self, idx: usize) -> Hash {
+        assert!(idx < self.num_leaves, "leaf index out of bounds");
+        self.levels[0][idx]
+    }
+
+    /// Inclusion proof of the leaf at the given index.
+    pub fn proof(&self, idx: usize) -> MerkleProof {
+        assert!(idx < self.num_leaves, "leaf index out of bounds");
+
+        let siblings = self.levels[..self.levels.len() - 1]
+            .iter()
+            .enumerate()
+            .map(|(height, level)| level[(idx >> height) ^ 1])
+            .collect();
+
+        MerkleProof {
+            idx,
+            num_leaves: self.num_leaves,
+            siblings,
+        }
+    }
+}
+
+/// Root of a subtree of the given height that only consists of padding.
+pub fn empty_root(height: u32) -> Hash {
+    (0..height).fold([0u8; 32], |node, _| node_hash(&node, &node))
+}
+
+/// Top node of the subtree whose nodes at some height are the given ones, padded with `padding` up
+/// to `2^levels` nodes, where the padding is the [`empty_root`] at that height. The top node of the
+/// whole tree is its root along with the number of leaves, see [`root_hash`].
+pub fn subtree_root(nodes: &[Hash], padding: Hash, levels: u32) -> Hash {
+    let mut level = nodes.to_vec();
+    level.resize(1 << levels, padding);
+    while level.len() > 1 {
+        level = level
+            .chunks_exact(2)
+            .map(|pair| node_hash(&pair[0], &pair[1]))
+            .collect();
+    }
+    level[0]
+}
+
+/// Inclusion proof of a leaf within a [`MerkleTree`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    /// Index of the leaf.
+    pub idx: usize,
+    /// Number of leaves of the tree, without the padding.
+    pub num_leaves: usize,
+    /// Sibling of each node on the path from the leaf to the root.
+    pub siblings: Vec<Hash>,
+}
+
+impl MerkleProof {
+    /// Whether the leaf is at the index of this proof within the tree with the given root, which
+    /// must have the number of leaves of this proof.
+    pub fn verify(&self, leaf: &Hash, root: &Hash) -> bool {
+        // the index must be one of the leaves, and fit into the path, otherwise multiple indices
+        // share the same proof
+        if self.idx >= self.num_leaves
+            || self
+                .idx
+                .checked_shr(self.siblings.len() as u32)
+                .unwrap_or(0)
+                != 0
+        {
+            return false;
+        }
+
+        let node = self
+            .siblings
+            .iter()
+            .enumerate()
+            .fold(*leaf, |node, (height, sibling)| {
+                if (self.idx >> height) & 1 == 0 {
+                    node_hash(&node, sibling)
+                } else {
+                    node_hash(sibling, &node)
+                }
+            });
+        root_hash(&node, self.num_leaves) == *root
+    }
+}
+
+/// Checks that each sample along with its metadata & payload hash is within the dataset with the
+/// given root, quantized with the given parameters if any, and returns the hash of each sample.
+pub fn verify_inclusion<T: Element>(
+    samples: &EmbeddingMatrix<T>,
+    quantization: Option<&Quantization>,
+    metadata: &[Metadata],
+    payloads: &[Hash],
+    root: &Hash,
+    proofs: &[MerkleProof],
+) -> Result<Vec<Hash>, VnnsError> {
+    if metadata.len() != samples.len() {
+        return Err(VnnsError::InvalidMetadata);
+    }
+    if payloads.len() != samples.len() {
+        return Err(VnnsError::InvalidPayloads);
+    }
+
+    (0..samples.len())
+        .map(|idx| {
+            let hash = item_hash(
+                samples.row(idx),
+                quantization,
+                &metadata[idx],
+                &payloads[idx],
+            );
+            match proofs.get(idx) {
+                Some(proof) if proof.verify(&hash, root) => Ok(hash),
+                _ => Err(VnnsError::InvalidInclusionProof { idx }),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(n: u8) -> Vec<Hash> {
+        (0..n)
+            .map(|i| item_hash(&[i as i16], None, &Metadata::default(), &[0; 32]))
+            .collect()
+    }
+
+    #[test]
+    fn test_merkle_tree() {
+        for n in 1..=9 {
+            let leaves = leaves(n);
+            let tree = MerkleTree::new(leaves.clone());
+            assert_eq!(tree.num_leaves(), n as usize);
+
+            for (idx, leaf) in leaves.iter().enumerate() {
+                assert_eq!(tree.leaf(idx), *leaf);
+                let proof = tree.proof(idx);
+                assert!(proof.verify(leaf, &tree.root()), "{} of {}", idx, n);
+
+                // another leaf, or another index
+                let other = leaves[(idx + 1) % leaves.len()];
+                assert_eq!(proof.verify(&other, &tree.root()), other == *leaf);
+                let moved = MerkleProof {
+                    idx: idx ^ 1,
+                    ..proof.clone()
+                };
+                assert!(!moved.verify(leaf, &tree.root()));
+                let out_of_range = MerkleProof {
+                    idx: idx + (1 << proof.siblings.len()),
+                    ..proof.clone()
+                };
+                assert!(!out_of_range.verify(leaf, &tree.root()));
+
+                // the same path within a tree of another size
+                let resized = MerkleProof {
+                    num_leaves: n as usize + 1,
+                    ..proof
+                };
+                assert!(!resized.verify(leaf, &tree.root()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_verify_inclusion() {
+        let samples = EmbeddingMatrix::from_rows(&[[1i16, 2], [3, 4], [5, 6]]).unwrap();
+        let metadata = vec![Metadata::default(); 3];
+        let payloads = [[7; 32], [8; 32], [9; 32]];
+        let hashes = (0..3)
+            .map(|idx| item_hash(samples.row(idx), None, &metadata[idx], &payloads[idx]))
+            .collect::<Vec<_>>();
+        let tree = MerkleTree::new(hashes.clone());
+        let root = tree.root();
+
+        // a chunk of the dataset, in any order
+        let chunk = samples.select(&[2, 0]);
+        let chunk_payloads = [payloads[2], payloads[0]];
+        let proofs = vec![tree.proof(2), tree.proof(0)];
+        assert_eq!(
+            verify_inclusion(
+                &chunk,
+                None,
+                &metadata[..2],
+                &chunk_payloads,
+                &root,
+                &proofs
+            ),
+            Ok(vec![hashes[2], hashes[0]])
+        );
+        assert_eq!(
+            verify_inclusion(
+                &chunk,
+                None,
+                &metadata[..2],
+                &chunk_payloads,
+                &root,
+                &proofs[..1]
+            ),
+            Err(VnnsError::InvalidInclusionProof { idx: 1 })
+        );
+        assert_eq!(
+            verify_inclusion(
+                &samples.select(&[1]),
+                None,
+                &metadata[..1],
+                &payloads[1..2],
+                &root,
+                &proofs
+            ),
+            Err(VnnsError::InvalidInclusionProof { idx: 0 })
+        );
+        assert_eq!(
+            verify_inclusion(&chunk, None, &metadata, &chunk_payloads, &root, &proofs),
+            Err(VnnsError::InvalidMetadata)
+        );
+
+        // the same embedding with another payload, or no payload at all
+        assert_eq!(
+            verify_inclusion(
+                &chunk,
+                None,
+                &metadata[..2],
+                &[payloads[2], [0; 32]],
+                &root,
+                &proofs
+            ),
+            Err(VnnsError::InvalidInclusionProof { idx: 1 })
+        );
+        assert_eq!(
+            verify_inclusion(&chunk, None, &metadata[..2], &payloads[..1], &root, &proofs),
+            Err(VnnsError::InvalidPayloads)
+        );
+
+        // the same levels under another scale are another dataset
+        let quantization = Quantization::new(16, 0.5);
+        let tree = MerkleTree::new(
+            (0..3)
+                .map(|idx| {
+                    item_hash(
+                        samples.row(idx),
+                        Some(&quantization),
+                        &metadata[idx],
+                        &payloads[idx],
+                    )
+                })
+                .collect(),
+        );
+        let quantized = |quantization: Quantization| {
+            verify_inclusion(
+                &chunk,
+                Some(&quantization),
+                &metadata[..2],
+                &chunk_payloads,
+                &tree.root(),
+                &[tree.proof(2), tree.proof(0)],
+            )
+        };
+        assert!(quantized(quantization).is_ok());
+        assert_eq!(
+            quantized(Quantization::new(16, 0.25)),
+            Err(VnnsError::InvalidInclusionProof { idx: 0 })
+        );
+    }
+
+    #[test]
+    fn test_merkle_root() {
+        let leaves = leaves(3);
+        let node = node_hash(
+            &node_hash(&leaves[0], &leaves[1]),
+            &node_hash(&leaves[2], &[0u8; 32]),
+        );
+        assert_eq!(MerkleTree::new(leaves.clone()).root(), root_hash(&node, 3));
+        assert_eq!(
+            MerkleTree::new(leaves[..1].to_vec()).root(),
+            root_hash(&leaves[0], 1)
+        );
+        assert_eq!(MerkleTree::new(Vec::new()).root(), root_hash(&[0u8; 32], 0));
+
+        // padding leaves are not leaves of the tree
+        let padded = leaves.iter().copied().chain([[0u8; 32]]).collect();
+        assert_ne!(
+            MerkleTree::new(padded).root(),
+            MerkleTree::new(leaves).root()
+        );
+    }
+
+    #[test]
+    fn test_subtree_root() {
+        // the tree over 5 leaves, from its aligned subtrees of height 1 & 2
+        let leaves = leaves(5);
+        let tree = MerkleTree::new(leaves.clone());
+        let pairs = leaves
+            .chunks(2)
+            .map(|pair| subtree_root(pair, empty_root(0), 1))
+            .collect::<Vec<_>>();
+        let quads = pairs
+            .chunks(2)
+            .map(|pair| subtree_root(pair, empty_root(1), 1))
+            .collect::<Vec<_>>();
+        let root = |node: Hash| root_hash(&node, 5);
+        assert_eq!(root(subtree_root(&quads, empty_root(2), 1)), tree.root());
+        assert_eq!(root(subtree_root(&pairs, empty_root(1), 2)), tree.root());
+        assert_eq!(root(subtree_root(&leaves, empty_root(0), 3)), tree.root());
+        assert_eq!(
+            root_hash(&empty_root(2), 4),
+            MerkleTree::new(vec![[0u8; 32]; 4]).root()
+        );
+    }
+}
@@ -0,0 +1,224 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::VnnsError;
+
+/// A distance metric between two vectors.
+///
+/// Every metric is expressed as a distance, i.e. a lower value means the vectors are closer. For
+/// similarity measures such as cosine & inner product, the similarity is negated or subtracted from
+/// one accordingly, so that all metrics can be minimized the same way.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum Metric {
+    /// Squared Euclidean distance, ranks the same as [`Metric::L2`] without the `sqrt`.
+    #[default]
+    SquaredL2 = 0,
+    /// Euclidean distance.
+    L2 = 1,
+    /// Cosine distance, `1 - cos(a, b)`.
+    Cosine = 2,
+    /// Negated inner product (dot product), `-(a . b)`.
+    InnerProduct = 3,
+    /// Manhattan distance, sum of absolute differences.
+    Manhattan = 4,
+}
+
+impl Metric {
+    /// All supported metrics.
+    pub const ALL: [Metric; 5] = [
+        Metric::SquaredL2,
+        Metric::L2,
+        Metric::Cosine,
+        Metric::InnerProduct,
+        Metric::Manhattan,
+    ];
+
+    /// Compute the distance between two vectors w.r.t this metric.
+    ///
+    /// Assumes that both vectors have the same length.
+    pub fn distance(&self, a: &[f32], b: &[f32]) -> f32 {
+        match self {
+            Metric::SquaredL2 => squared_l2(a, b),
+            Metric::L2 => squared_l2(a, b).sqrt(),
+            Metric::Cosine => {
+                let norms = dot(a, a).sqrt() * dot(b, b).sqrt();
+                if norms == 0.0 {
+                    // a zero vector is not similar to anything
+                    1.0
+                } else {
+                    1.0 - dot(a, b) / norms
+                }
+            }
+            Metric::InnerProduct => -dot(a, b),
+            Metric::Manhattan => a.iter().zip(b).map(|(a, b)| (a - b).abs()).sum(),
+        }
+    }
+
+    /// Compute the distance between two quantized vectors w.r.t this metric, using integer
+    /// arithmetic only.
+    ///
+    /// Cosine distance is returned in fixed-point with [`COSINE_FRACTION_BITS`] fractional bits, the
+    /// remaining metrics are exact. Assumes that both vectors have the same length.
+    pub fn distance_quantized(&self, a: &[i16], b: &[i16]) -> i64 {
+        match self {
+            Metric::SquaredL2 => squared_l2_quantized(a, b),
+            Metric::L2 => isqrt(squared_l2_quantized(a, b) as u128) as i64,
+            Metric::Cosine => {
+                let norms = isqrt(dot_quantized(a, a) as u128 * dot_quantized(b, b) as u128);
+                if norms == 0 {
+                    // a zero vector is not similar to anything
+                    1 << COSINE_FRACTION_BITS
+                } else {
+                    let norms = norms as i128;
+                    let diff = (norms - dot_quantized(a, b) as i128) << COSINE_FRACTION_BITS;
+                    (diff / norms) as i64
+                }
+            }
+            Metric::InnerProduct => -dot_quantized(a, b),
+            Metric::Manhattan => a
+                .iter()
+                .zip(b)
+                .map(|(a, b)| (*a as i64 - *b as i64).abs())
+                .sum(),
+        }
+    }
+
+    /// Name of the metric, as accepted by [`Metric::from_str`].
+    pub fn name(&self) -> &'static str {
+        match self {
+            Metric::SquaredL2 => "squared-l2",
+            Metric::L2 => "l2",
+            Metric::Cosine => "cosine",
+            Metric::InnerProduct => "inner-product",
+            Metric::Manhattan => "manhattan",
+        }
+    }
+}
+
+/// Number of fractional bits in the fixed-point cosine distance of quantized vectors.
+pub const COSINE_FRACTION_BITS: u32 = 16;
+
+#[inline]
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+#[inline]
+fn squared_l2(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| (a - b).powi(2)).sum()
+}
+
+#[inline]
+fn dot_quantized(a: &[i16], b: &[i16]) -> i64 {
+    a.iter().zip(b).map(|(a, b)| *a as i64 * *b as i64).sum()
+}
+
+#[inline]
+fn squared_l2_quantized(a: &[i16], b: &[i16]) -> i64 {
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| (*a as i64 - *b as i64).pow(2))
+        .sum()
+}
+
+/// Integer square root, rounded down.
+fn isqrt(n: u128) -> u128 {
+    if n < 2 {
+        return n;
+    }
+
+    // Newton's method, starting from an over-estimate
+    let mut x = 1u128 << ((128 - n.leading_zeros()).div_ceil(2));
+    loop {
+        let y = (x + n / x) / 2;
+        if y >= x {
+            return x;
+        }
+        x = y;
+    }
+}
+
+impl From<Metric> for u8 {
+    fn from(metric: Metric) -> Self {
+        metric as u8
+    }
+}
+
+impl TryFrom<u8> for Metric {
+    type Error = VnnsError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Metric::ALL
+            .into_iter()
+            .find(|metric| *metric as u8 == value)
+            .ok_or(VnnsError::UnknownMetric(value))
+    }
+}
+
+impl FromStr for Metric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Metric::ALL
+            .into_iter()
+            .find(|metric| metric.name() == s)
+            .ok_or_else(|| {
+                let names = Metric::ALL.map(|metric| metric.name()).join(", ");
+                format!("unknown metric '{}', expected one of: {}", s, names)
+            })
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distances() {
+        let a = [1.0, 0.0];
+        let b = [0.0, 2.0];
+        assert_eq!(Metric::SquaredL2.distance(&a, &b), 5.0);
+        assert_eq!(Metric::L2.distance(&a, &b), 5.0f32.sqrt());
+        assert_eq!(Metric::Cosine.distance(&a, &b), 1.0);
+        assert_eq!(Metric::Cosine.distance(&a, &a), 0.0);
+        assert_eq!(Metric::InnerProduct.distance(&b, &b), -4.0);
+        assert_eq!(Metric::Manhattan.distance(&a, &b), 3.0);
+    }
+
+    #[test]
+    fn test_quantized_distances() {
+        let a = [100, 0];
+        let b = [0, 200];
+        assert_eq!(Metric::SquaredL2.distance_quantized(&a, &b), 50_000);
+        assert_eq!(Metric::L2.distance_quantized(&a, &b), 223);
+        assert_eq!(Metric::Cosine.distance_quantized(&a, &b), 1 << 16);
+        assert_eq!(Metric::Cosine.distance_quantized(&a, &a), 0);
+        assert_eq!(Metric::InnerProduct.distance_quantized(&b, &b), -40_000);
+        assert_eq!(Metric::Manhattan.distance_quantized(&a, &b), 300);
+    }
+
+    #[test]
+    fn test_isqrt() {
+        for n in [0u128, 1, 2, 3, 4, 15, 16, 17, 1 << 40, u64::MAX as u128] {
+            let root = isqrt(n);
+            assert!(root * root <= n && (root + 1) * (root + 1) > n);
+        }
+    }
+
+    #[test]
+    fn test_metric_ids() {
+        for metric in Metric::ALL {
+            assert_eq!(Metric::try_from(u8::from(metric)), Ok(metric));
+            assert_eq!(metric.name().parse::<Metric>(), Ok(metric));
+        }
+        assert!(Metric::try_from(255).is_err());
+        assert!("hamming".parse::<Metric>().is_err());
+    }
+}
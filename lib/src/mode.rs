@@ -0,0 +1,79 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// What the search returns for a query.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SearchMode {
+    /// The `k` closest samples, in order.
+    TopK { k: u32 },
+    /// All samples within distance `radius` (inclusive) of the query, in dataset order.
+    Range { radius: f32 },
+}
+
+impl SearchMode {
+    /// Id of the top-k mode within the public values.
+    pub const TOP_K_ID: u8 = 0;
+    /// Id of the range mode within the public values.
+    pub const RANGE_ID: u8 = 1;
+
+    /// Id of the mode within the public values.
+    pub fn id(&self) -> u8 {
+        match self {
+            SearchMode::TopK { .. } => Self::TOP_K_ID,
+            SearchMode::Range { .. } => Self::RANGE_ID,
+        }
+    }
+
+    /// Byte representation of the mode, to be committed by the program.
+    ///
+    /// ( id || k as u32 ) or ( id || radius as f32 )
+    pub fn to_bytes(&self) -> [u8; 5] {
+        let mut bytes = [0u8; 5];
+        bytes[0] = self.id();
+        match self {
+            SearchMode::TopK { k } => bytes[1..].copy_from_slice(&k.to_le_bytes()),
+            SearchMode::Range { radius } => bytes[1..].copy_from_slice(&radius.to_le_bytes()),
+        }
+        bytes
+    }
+
+    /// Decodes a mode encoded with [`SearchMode::to_bytes`], or `None` if the id is not known.
+    pub fn from_bytes(bytes: [u8; 5]) -> Option<Self> {
+        let param = bytes[1..].try_into().expect("expected 4 bytes");
+        match bytes[0] {
+            Self::TOP_K_ID => Some(SearchMode::TopK {
+                k: u32::from_le_bytes(param),
+            }),
+            Self::RANGE_ID => Some(SearchMode::Range {
+                radius: f32::from_le_bytes(param),
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SearchMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SearchMode::TopK { k } => write!(f, "top-{}", k),
+            SearchMode::Range { radius } => write!(f, "range <= {}", radius),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mode_bytes() {
+        for mode in [
+            SearchMode::TopK { k: 3 },
+            SearchMode::Range { radius: 0.25 },
+        ] {
+            assert_eq!(SearchMode::from_bytes(mode.to_bytes()), Some(mode));
+        }
+        assert_eq!(SearchMode::from_bytes([2, 0, 0, 0, 0]), None);
+    }
+}
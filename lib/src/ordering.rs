@@ -0,0 +1,71 @@
+//! Total order of the search results, shared by the host & the zkVM program.
+//!
+//! Samples are ordered by their distance to the query, where:
+//!
+//! - NaN is farther than any other distance, regardless of its sign or payload,
+//! - `-0.0` and `+0.0` are equal,
+//! - equal distances are ordered by their index, i.e. the lowest index wins.
+//!
+//! Within the tournament, the contestants of each round are kept in dataset order, so the lowest
+//! index within a chunk is also the lowest index within the dataset.
+
+use std::cmp::Ordering;
+
+/// A distance that can be ordered totally.
+pub trait Distance: Copy {
+    /// Compares two distances, see the [module documentation](self) for the order.
+    fn cmp_distance(&self, other: &Self) -> Ordering;
+}
+
+impl Distance for f32 {
+    fn cmp_distance(&self, other: &Self) -> Ordering {
+        match (self.is_nan(), other.is_nan()) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Greater,
+            (false, true) => Ordering::Less,
+            // treats -0.0 and +0.0 as equal, unlike `f32::total_cmp`
+            (false, false) => self.partial_cmp(other).expect("not NaN"),
+        }
+    }
+}
+
+impl Distance for i64 {
+    fn cmp_distance(&self, other: &Self) -> Ordering {
+        self.cmp(other)
+    }
+}
+
+/// Compares two `(index, distance)` pairs, by distance first and index second.
+pub fn cmp_ranked<D: Distance>(a: (usize, D), b: (usize, D)) -> Ordering {
+    a.1.cmp_distance(&b.1).then(a.0.cmp(&b.0))
+}
+
+/// Returns the indices of the `k` smallest distances, in order.
+pub(crate) fn top_k_indices<D: Distance>(distances: &[D], k: usize) -> Vec<usize> {
+    let mut idxs = (0..distances.len()).collect::<Vec<_>>();
+    idxs.sort_unstable_by(|a, b| cmp_ranked((*a, distances[*a]), (*b, distances[*b])));
+    idxs.truncate(k);
+
+    idxs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cmp_distance() {
+        assert_eq!(f32::NAN.cmp_distance(&f32::INFINITY), Ordering::Greater);
+        assert_eq!((-f32::NAN).cmp_distance(&f32::INFINITY), Ordering::Greater);
+        assert_eq!(f32::NAN.cmp_distance(&-f32::NAN), Ordering::Equal);
+        assert_eq!((-0.0f32).cmp_distance(&0.0), Ordering::Equal);
+        assert_eq!(1.0f32.cmp_distance(&2.0), Ordering::Less);
+    }
+
+    #[test]
+    fn test_top_k_indices() {
+        let distances = [f32::NAN, 1.0, -0.0, 0.0, -f32::NAN, 1.0, f32::NEG_INFINITY];
+        assert_eq!(top_k_indices(&distances, 7), vec![6, 2, 3, 1, 5, 0, 4]);
+        assert_eq!(top_k_indices(&[3i64, 1, 1, 2], 3), vec![1, 2, 3]);
+    }
+}
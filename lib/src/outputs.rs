@@ -0,0 +1,459 @@
+//! Public values committed by the VNNS program.
+//!
+//! The layout starts with a version byte, so that a change of the layout is detected when decoding
+//! public values that were committed by an older program, e.g. from a `.pub` file.
+
+use std::fmt;
+use std::ops::Range;
+
+use crate::encoding::{decode_optional_score, encode_index, encode_optional_score, Reader};
+use crate::score::{margin, score_to_distance};
+use crate::{Filter, Hash, MerkleProof, Metric, Quantization, Score, SearchMode, STATUS_OK};
+
+/// Public values committed by the VNNS program.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PublicOutputs {
+    /// Status code, see [`crate::VnnsError::code`].
+    pub status: u8,
+    /// Hash of the query, salted or not, see [`crate::encoding::query_commitment`].
+    pub query_commitment: Hash,
+    /// Merkle root of the dataset, which commits to its size, see [`crate::MerkleTree`].
+    pub dataset_root: Hash,
+    /// Number of samples within the dataset, the same as the one its root commits to.
+    pub dataset_size: usize,
+    /// Verifying key digest of the program, which has verified the proofs of the previous round.
+    pub program_vkey: [u32; 8],
+    /// Range of dataset indices whose samples took part in the search, either directly or through
+    /// the previous rounds. The results of a search that covers the whole dataset are the results
+    /// for the whole dataset.
+    pub covered: Range<usize>,
+    /// Id of the metric, which is committed as-is even if it is not known.
+    pub metric_id: u8,
+    pub mode: SearchMode,
+    /// Quantization parameters, or `None` if the samples are not quantized.
+    pub quantization: Option<Quantization>,
+    /// Filter, or `None` if there is none. A malformed filter is rejected with no filter committed.
+    pub filter: Option<Filter>,
+    /// Dataset indices of the closest samples in order, or of the samples within the radius in
+    /// dataset order, along with their output commitments.
+    pub results: Vec<(usize, Hash)>,
+    /// Score of each result, in the same order as the results, see [`crate::Score`].
+    pub scores: Vec<Score>,
+    /// Score of the closest sample that took part in the search but is not a result, or `None` if
+    /// there is no such sample.
+    pub runner_up: Option<Score>,
+}
+
+impl PublicOutputs {
+    /// Version of the layout, to be incremented whenever it changes.
+    pub const VERSION: u8 = 4;
+
+    /// Byte representation of the public values, to be committed by the program.
+    ///
+    /// ( version || status || query_commitment || dataset_root || dataset_size as u32 ||
+    /// program_vkey as u32s || covered.start as u32 || covered.end as u32 || metric_id || mode ||
+    /// quantization (zero if none) || filter_len as u32 || filter || count as u32 ||
+    /// idx[0] as u32 || output_commitment[0] || score[0] as i64 || .. || runner_up as optional
+    /// score ), see [`crate::encoding::encode_optional_score`].
+    pub fn encode(&self) -> Vec<u8> {
+        assert_eq!(
+            self.scores.len(),
+            self.results.len(),
+            "expected a score per result"
+        );
+        let filter_bytes = self
+            .filter
+            .as_ref()
+            .map(Filter::to_bytes)
+            .unwrap_or_default();
+
+        let mut bytes = vec![Self::VERSION, self.status];
+        bytes.extend_from_slice(&self.query_commitment);
+        bytes.extend_from_slice(&self.dataset_root);
+        bytes.extend_from_slice(&encode_index(self.dataset_size));
+        for word in self.program_vkey {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        bytes.extend_from_slice(&encode_index(self.covered.start));
+        bytes.extend_from_slice(&encode_index(self.covered.end));
+        bytes.push(self.metric_id);
+        bytes.extend_from_slice(&self.mode.to_bytes());
+        bytes.extend_from_slice(
+            &self
+                .quantization
+                .map(|quantization| quantization.to_bytes())
+                .unwrap_or_default(),
+        );
+        bytes.extend_from_slice(&encode_index(filter_bytes.len()));
+        bytes.extend_from_slice(&filter_bytes);
+        bytes.extend_from_slice(&encode_index(self.results.len()));
+        for ((idx, output_commitment), score) in self.results.iter().zip(&self.scores) {
+            bytes.extend_from_slice(&encode_index(*idx));
+            bytes.extend_from_slice(output_commitment);
+            bytes.extend_from_slice(&score.to_le_bytes());
+        }
+        bytes.extend_from_slice(&encode_optional_score(self.runner_up));
+        bytes
+    }
+
+    /// Decodes the public values encoded with [`PublicOutputs::encode`].
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = Reader::new(bytes);
+
+        let version = reader.read_u8().ok_or(DecodeError::Truncated)?;
+        if version != Self::VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+
+        let status = reader.read_u8().ok_or(DecodeError::Truncated)?;
+        let query_commitment = reader.read_array().ok_or(DecodeError::Truncated)?;
+        let dataset_root = reader.read_array().ok_or(DecodeError::Truncated)?;
+        let dataset_size = reader.read_u32().ok_or(DecodeError::Truncated)? as usize;
+        let mut program_vkey = [0u32; 8];
+        for word in &mut program_vkey {
+            *word = reader.read_u32().ok_or(DecodeError::Truncated)?;
+        }
+        let start = reader.read_u32().ok_or(DecodeError::Truncated)? as usize;
+        let end = reader.read_u32().ok_or(DecodeError::Truncated)? as usize;
+        let metric_id = reader.read_u8().ok_or(DecodeError::Truncated)?;
+
+        let mode_bytes = reader.read_array().ok_or(DecodeError::Truncated)?;
+        let mode =
+            SearchMode::from_bytes(mode_bytes).ok_or(DecodeError::UnknownMode(mode_bytes[0]))?;
+
+        let quantization = match reader.read_array().ok_or(DecodeError::Truncated)? {
+            [0, 0, 0, 0, 0] => None,
+            bytes => Some(Quantization::from_bytes(bytes).ok_or(DecodeError::InvalidQuantization)?),
+        };
+
+        let filter_len = reader.read_u32().ok_or(DecodeError::Truncated)? as usize;
+        let filter_bytes = reader
+            .read_slice(filter_len)
+            .ok_or(DecodeError::Truncated)?;
+        let filter = if filter_bytes.is_empty() {
+            None
+        } else {
+            Some(Filter::from_bytes(filter_bytes).map_err(|_| DecodeError::InvalidFilter)?)
+        };
+
+        let count = reader.read_u32().ok_or(DecodeError::Truncated)? as usize;
+        let (results, scores) = (0..count)
+            .map(|_| {
+                let idx = reader.read_u32()? as usize;
+                Some(((idx, reader.read_array()?), reader.read_i64()?))
+            })
+            .collect::<Option<(Vec<_>, Vec<_>)>>()
+            .ok_or(DecodeError::Truncated)?;
+        let runner_up = decode_optional_score(reader.read_array().ok_or(DecodeError::Truncated)?)
+            .ok_or(DecodeError::InvalidScore)?;
+
+        if !reader.is_empty() {
+            return Err(DecodeError::TrailingBytes);
+        }
+
+        Ok(Self {
+            status,
+            query_commitment,
+            dataset_root,
+            dataset_size,
+            program_vkey,
+            covered: start..end,
+            metric_id,
+            mode,
+            quantization,
+            filter,
+            results,
+            scores,
+            runner_up,
+        })
+    }
+
+    /// Whether both are chunks of the same search, i.e. everything but the status, the covered
+    /// range & the results is the same.
+    pub fn is_same_search(&self, other: &Self) -> bool {
+        self.query_commitment == other.query_commitment
+            && self.dataset_root == other.dataset_root
+            && self.dataset_size == other.dataset_size
+            && self.program_vkey == other.program_vkey
+            && self.metric_id == other.metric_id
+            && self.mode.to_bytes() == other.mode.to_bytes()
+            && self
+                .quantization
+                .map(|quantization| quantization.to_bytes())
+                == other
+                    .quantization
+                    .map(|quantization| quantization.to_bytes())
+            && self.filter == other.filter
+    }
+
+    /// Whether the search covers the whole dataset, i.e. it is the final one.
+    pub fn covers_dataset(&self) -> bool {
+        self.covered == (0..self.dataset_size)
+    }
+
+    /// Whether the program has rejected its input.
+    pub fn is_rejected(&self) -> bool {
+        self.status != STATUS_OK
+    }
+
+    /// Returns the result indices, in order.
+    pub fn indices(&self) -> Vec<usize> {
+        self.results.iter().map(|(idx, _)| *idx).collect()
+    }
+
+    /// Margin between the farthest result & the runner-up in score units, or `None` if there is
+    /// no result or no runner-up. Within the final proof, this is the gap between the last result &
+    /// the closest sample of the whole dataset that is not a result.
+    pub fn margin(&self) -> Option<Score> {
+        margin(&self.scores, self.runner_up)
+    }
+
+    /// Maps a score, or a margin, back to a distance w.r.t the committed metric, or `None` if the
+    /// metric is not known.
+    pub fn distance(&self, score: Score) -> Option<f64> {
+        let metric = Metric::try_from(self.metric_id).ok()?;
+        Some(match self.quantization {
+            Some(quantization) => quantization.dequantize_distance(score, metric),
+            None => score_to_distance(score),
+        })
+    }
+
+    /// Whether the item with the given hash is a result at the given dataset index, and is within
+    /// the dataset at that index by its inclusion proof, so that a result can be opened without
+    /// the rest of the dataset.
+    pub fn is_result(&self, idx: usize, item_hash: &Hash, proof: &MerkleProof) -> bool {
+        proof.idx == idx
+            && self.results.contains(&(idx, *item_hash))
+            && proof.verify(item_hash, &self.dataset_root)
+    }
+}
+
+/// Errors that can occur when decoding [`PublicOutputs`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The public values were committed with another layout.
+    UnsupportedVersion(u8),
+    /// The public values end before the last field.
+    Truncated,
+    /// There are bytes after the last field.
+    TrailingBytes,
+    /// The mode id is not known.
+    UnknownMode(u8),
+    /// The quantization parameters are neither zero nor valid.
+    InvalidQuantization,
+    /// The filter is malformed.
+    InvalidFilter,
+    /// The flag of an optional score is neither zero nor one.
+    InvalidScore,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnsupportedVersion(version) => write!(
+                f,
+                "public values have version {}, expected {}",
+                version,
+                PublicOutputs::VERSION
+            ),
+            DecodeError::Truncated => write!(f, "public values are truncated"),
+            DecodeError::TrailingBytes => write!(f, "public values have trailing bytes"),
+            DecodeError::UnknownMode(id) => write!(f, "unknown mode id: {}", id),
+            DecodeError::InvalidQuantization => write!(f, "malformed quantization parameters"),
+            DecodeError::InvalidFilter => write!(f, "malformed filter"),
+            DecodeError::InvalidScore => write!(f, "malformed optional score"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Public values of a successful chunk of a top-k search, as a fixture of the tests, with the given
+/// winners whose hashes & scores derive from their indices, and the end of the covered range as
+/// the score of the runner-up.
+#[cfg(test)]
+pub(crate) fn test_chunk(covered: Range<usize>, winners: &[usize]) -> PublicOutputs {
+    PublicOutputs {
+        runner_up: Some(covered.end as Score),
+        status: STATUS_OK,
+        query_commitment: [1; 32],
+        dataset_root: [2; 32],
+        dataset_size: 12,
+        program_vkey: [3; 8],
+        covered,
+        metric_id: 0,
+        mode: SearchMode::TopK { k: 1 },
+        quantization: None,
+        filter: None,
+        results: winners.iter().map(|&idx| (idx, [idx as u8; 32])).collect(),
+        scores: winners.iter().map(|&idx| idx as Score).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MerkleTree;
+
+    fn sample_outputs() -> PublicOutputs {
+        PublicOutputs {
+            status: STATUS_OK,
+            query_commitment: [1; 32],
+            dataset_root: [2; 32],
+            dataset_size: 16,
+            program_vkey: [1, 2, 3, 4, 5, 6, 7, 8],
+            covered: 4..12,
+            metric_id: 3,
+            mode: SearchMode::TopK { k: 2 },
+            quantization: Some(Quantization {
+                bits: 8,
+                scale: 0.5,
+            }),
+            filter: Some(Filter::new(vec!["price=..100".parse().unwrap()])),
+            results: vec![(7, [4; 32]), (0, [5; 32])],
+            scores: vec![-3, 2],
+            runner_up: Some(10),
+        }
+    }
+
+    #[test]
+    fn test_public_outputs() {
+        let outputs = sample_outputs();
+        let bytes = outputs.encode();
+        assert_eq!(PublicOutputs::decode(&bytes), Ok(outputs.clone()));
+        assert_eq!(outputs.indices(), vec![7, 0]);
+        assert_eq!(outputs.margin(), Some(8));
+        assert_eq!(outputs.distance(8), Some(2.0));
+
+        // fixed offsets of the header
+        assert_eq!(bytes[0], PublicOutputs::VERSION);
+        assert_eq!(&bytes[66..70], &[16, 0, 0, 0]);
+        assert_eq!(&bytes[70..74], &[1, 0, 0, 0]);
+        assert_eq!(&bytes[102..110], &[4, 0, 0, 0, 12, 0, 0, 0]);
+        assert_eq!(bytes[110], 3);
+        assert_eq!(&bytes[111..116], &outputs.mode.to_bytes());
+        assert!(!outputs.covers_dataset());
+
+        let plain = PublicOutputs {
+            status: 9,
+            quantization: None,
+            filter: None,
+            results: Vec::new(),
+            scores: Vec::new(),
+            runner_up: None,
+            ..outputs
+        };
+        let plain_bytes = plain.encode();
+        assert_eq!(
+            plain_bytes.len(),
+            1 + 1 + 32 + 32 + 4 + 32 + 8 + 1 + 5 + 5 + 4 + 4 + 9
+        );
+        assert_eq!(plain.margin(), None);
+        assert_eq!(plain.distance(1 << 31), Some(0.5));
+        assert_eq!(PublicOutputs::decode(&plain_bytes), Ok(plain.clone()));
+        assert!(plain.is_rejected());
+
+        // another chunk of the same search, unless anything but the results differs
+        let other_chunk = PublicOutputs {
+            covered: 0..4,
+            results: vec![(1, [6; 32])],
+            scores: vec![0],
+            ..sample_outputs()
+        };
+        assert!(sample_outputs().is_same_search(&other_chunk));
+        assert!(!plain.is_same_search(&other_chunk));
+        let other_query = PublicOutputs {
+            query_commitment: [0; 32],
+            ..sample_outputs()
+        };
+        assert!(!sample_outputs().is_same_search(&other_query));
+        let other_size = PublicOutputs {
+            dataset_size: 12,
+            ..sample_outputs()
+        };
+        assert!(!sample_outputs().is_same_search(&other_size));
+        assert!(PublicOutputs {
+            covered: 0..16,
+            ..sample_outputs()
+        }
+        .covers_dataset());
+    }
+
+    #[test]
+    fn test_is_result() {
+        let tree = MerkleTree::new(vec![[4; 32], [5; 32], [6; 32]]);
+        let outputs = PublicOutputs {
+            dataset_root: tree.root(),
+            dataset_size: 3,
+            results: vec![(2, [6; 32]), (0, [4; 32])],
+            scores: vec![0, 1],
+            ..sample_outputs()
+        };
+        assert!(outputs.is_result(2, &[6; 32], &tree.proof(2)));
+        assert!(outputs.is_result(0, &[4; 32], &tree.proof(0)));
+
+        // not a result, another item, or the proof of another index
+        assert!(!outputs.is_result(1, &[5; 32], &tree.proof(1)));
+        assert!(!outputs.is_result(2, &[5; 32], &tree.proof(2)));
+        assert!(!outputs.is_result(2, &[6; 32], &tree.proof(0)));
+    }
+
+    #[test]
+    fn test_public_outputs_errors() {
+        let bytes = sample_outputs().encode();
+
+        let mut other_version = bytes.clone();
+        other_version[0] = 0;
+        assert_eq!(
+            PublicOutputs::decode(&other_version),
+            Err(DecodeError::UnsupportedVersion(0))
+        );
+        assert_eq!(PublicOutputs::decode(&[]), Err(DecodeError::Truncated));
+        for len in [1, 70, 110, 124, bytes.len() - 1] {
+            assert_eq!(
+                PublicOutputs::decode(&bytes[..len]),
+                Err(DecodeError::Truncated),
+                "{}",
+                len
+            );
+        }
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            PublicOutputs::decode(&trailing),
+            Err(DecodeError::TrailingBytes)
+        );
+
+        let mut unknown_mode = bytes.clone();
+        unknown_mode[111] = 9;
+        assert_eq!(
+            PublicOutputs::decode(&unknown_mode),
+            Err(DecodeError::UnknownMode(9))
+        );
+
+        let mut invalid_quantization = bytes.clone();
+        invalid_quantization[116] = 0;
+        assert_eq!(
+            PublicOutputs::decode(&invalid_quantization),
+            Err(DecodeError::InvalidQuantization)
+        );
+
+        // the filter starts after its length, with its number of conditions
+        let mut invalid_filter = bytes.clone();
+        invalid_filter[125] = 2;
+        assert_eq!(
+            PublicOutputs::decode(&invalid_filter),
+            Err(DecodeError::InvalidFilter)
+        );
+
+        // the runner-up is last
+        let mut invalid_score = bytes;
+        let flag = invalid_score.len() - 9;
+        invalid_score[flag] = 2;
+        assert_eq!(
+            PublicOutputs::decode(&invalid_score),
+            Err(DecodeError::InvalidScore)
+        );
+    }
+}
@@ -0,0 +1,330 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ordering::top_k_indices, validate_dimensions, EmbeddingMatrix, Metric, Scored, VnnsError,
+    COSINE_FRACTION_BITS,
+};
+
+/// Quantization parameters of a dataset, such that `value ≈ quantized * scale`.
+///
+/// The quantized values are stored as `i16` regardless of the bit-width, and lie within the range
+/// `[-max_level, max_level]` for the respective bit-width.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Quantization {
+    /// Bit-width of the quantized values, either 8 or 16.
+    pub bits: u8,
+    /// Scale of the quantized values.
+    pub scale: f32,
+}
+
+impl Quantization {
+    /// Supported bit-widths.
+    pub const BITS: [u8; 2] = [8, 16];
+
+    pub fn new(bits: u8, scale: f32) -> Self {
+        assert!(Self::BITS.contains(&bits), "unsupported bit-width");
+        assert!(scale.is_finite() && scale > 0.0, "scale must be positive");
+        Self { bits, scale }
+    }
+
+    /// Fits the quantization parameters to the given samples, such that the largest absolute value
+    /// within the samples is mapped to the largest quantization level.
+    pub fn fit(samples: &EmbeddingMatrix, bits: u8) -> Self {
+        let max_abs = samples
+            .as_slice()
+            .iter()
+            .fold(0.0f32, |max, value| max.max(value.abs()));
+
+        // fall back to unit scale for an all-zero dataset
+        let max_abs = if max_abs > 0.0 { max_abs } else { 1.0 };
+        Self::new(bits, max_abs / Self::max_level_for(bits) as f32)
+    }
+
+    /// Largest absolute quantization level.
+    pub fn max_level(&self) -> i16 {
+        Self::max_level_for(self.bits)
+    }
+
+    fn max_level_for(bits: u8) -> i16 {
+        ((1i32 << (bits - 1)) - 1) as i16
+    }
+
+    /// Quantizes a vector, rounding to the nearest level and clamping the out-of-range values.
+    pub fn quantize(&self, values: &[f32]) -> Vec<i16> {
+        let max_level = self.max_level() as f32;
+        values
+            .iter()
+            .map(|value| (value / self.scale).round().clamp(-max_level, max_level) as i16)
+            .collect()
+    }
+
+    /// Quantizes all rows of a matrix.
+    pub fn quantize_matrix(&self, samples: &EmbeddingMatrix) -> EmbeddingMatrix<i16> {
+        EmbeddingMatrix::new(samples.dim(), self.quantize(samples.as_slice()))
+            .expect("dimension is preserved")
+    }
+
+    /// Checks that the parameters are supported, i.e. a supported bit-width & a positive, finite
+    /// scale, as they are read by the program without the checks of [`Quantization::new`].
+    pub fn validate(&self) -> Result<(), VnnsError> {
+        if !Self::BITS.contains(&self.bits) || !self.scale.is_finite() || self.scale <= 0.0 {
+            return Err(VnnsError::InvalidQuantization);
+        }
+        Ok(())
+    }
+
+    /// Checks that the quantized values lie within `[-max_level, max_level]`, given that the
+    /// parameters themselves are valid.
+    pub fn validate_levels(&self, values: &[i16]) -> Result<(), VnnsError> {
+        let max_level = self.max_level();
+        if values
+            .iter()
+            .any(|value| !(-max_level..=max_level).contains(value))
+        {
+            return Err(VnnsError::InvalidQuantization);
+        }
+        Ok(())
+    }
+
+    /// Maps a quantized vector back to floating-point values.
+    pub fn dequantize(&self, values: &[i16]) -> Vec<f32> {
+        values
+            .iter()
+            .map(|value| *value as f32 * self.scale)
+            .collect()
+    }
+
+    /// Maps a distance between floating-point vectors to the corresponding distance between their
+    /// quantized vectors w.r.t the given metric, e.g. to quantize the radius of a range search.
+    pub fn quantize_distance(&self, distance: f32, metric: Metric) -> i64 {
+        let quantized = match metric {
+            Metric::SquaredL2 | Metric::InnerProduct => distance / (self.scale * self.scale),
+            Metric::L2 | Metric::Manhattan => distance / self.scale,
+            // cosine distance is independent of the scale
+            Metric::Cosine => distance * (1u64 << COSINE_FRACTION_BITS) as f32,
+        };
+
+        // saturates for out-of-range distances
+        quantized.round() as i64
+    }
+
+    /// Maps a distance between quantized vectors back to the corresponding distance between
+    /// floating-point vectors, i.e. the inverse of [`Quantization::quantize_distance`].
+    pub fn dequantize_distance(&self, distance: i64, metric: Metric) -> f64 {
+        let scale = self.scale as f64;
+        match metric {
+            Metric::SquaredL2 | Metric::InnerProduct => distance as f64 * scale * scale,
+            Metric::L2 | Metric::Manhattan => distance as f64 * scale,
+            Metric::Cosine => distance as f64 / (1u64 << COSINE_FRACTION_BITS) as f64,
+        }
+    }
+
+    /// Byte representation of the parameters, to be included in the samples commitment.
+    ///
+    /// ( bits || scale as f32 )
+    pub fn to_bytes(&self) -> [u8; 5] {
+        let mut bytes = [0u8; 5];
+        bytes[0] = self.bits;
+        bytes[1..].copy_from_slice(&self.scale.to_le_bytes());
+        bytes
+    }
+
+    /// Decodes the parameters encoded with [`Quantization::to_bytes`], or `None` if they are not
+    /// valid, e.g. all zeros.
+    pub fn from_bytes(bytes: [u8; 5]) -> Option<Self> {
+        let bits = bytes[0];
+        let scale = f32::from_le_bytes(bytes[1..].try_into().expect("expected 4 bytes"));
+        let quantization = Self { bits, scale };
+        quantization.validate().ok().map(|_| quantization)
+    }
+}
+
+/// Compute the `k` best samples from a list of quantized samples given a quantized query.
+///
+/// Same as [`crate::compute_top_k`], but uses integer arithmetic only. The samples and the query
+/// must be quantized with the same parameters.
+pub fn compute_top_k_quantized(
+    samples: &EmbeddingMatrix<i16>,
+    query: &[i16],
+    k: usize,
+    metric: Metric,
+) -> Result<Vec<usize>, VnnsError> {
+    compute_top_k_quantized_scored(samples, query, k, metric).map(|scored| scored.idxs)
+}
+
+/// Same as [`compute_top_k_quantized`], but also returns the score of each result & of the
+/// runner-up, in quantized units.
+pub fn compute_top_k_quantized_scored(
+    samples: &EmbeddingMatrix<i16>,
+    query: &[i16],
+    k: usize,
+    metric: Metric,
+) -> Result<Scored, VnnsError> {
+    validate_dimensions(samples, query)?;
+
+    let distances = samples
+        .rows()
+        .map(|sample| metric.distance_quantized(sample, query))
+        .collect::<Vec<_>>();
+
+    Ok(Scored::new(&distances, top_k_indices(&distances, k)))
+}
+
+/// Compute all quantized samples within the quantized distance `radius` of a quantized query.
+///
+/// Same as [`crate::range_search`], but uses integer arithmetic only. The radius can be obtained
+/// with [`Quantization::quantize_distance`].
+pub fn range_search_quantized(
+    samples: &EmbeddingMatrix<i16>,
+    query: &[i16],
+    radius: i64,
+    metric: Metric,
+) -> Result<Vec<usize>, VnnsError> {
+    range_search_quantized_scored(samples, query, radius, metric).map(|scored| scored.idxs)
+}
+
+/// Same as [`range_search_quantized`], but also returns the score of each result & of the
+/// runner-up, in quantized units.
+pub fn range_search_quantized_scored(
+    samples: &EmbeddingMatrix<i16>,
+    query: &[i16],
+    radius: i64,
+    metric: Metric,
+) -> Result<Scored, VnnsError> {
+    validate_dimensions(samples, query)?;
+
+    let distances = samples
+        .rows()
+        .map(|sample| metric.distance_quantized(sample, query))
+        .collect::<Vec<_>>();
+    let idxs = (0..distances.len())
+        .filter(|&idx| distances[idx] <= radius)
+        .collect();
+
+    Ok(Scored::new(&distances, idxs))
+}
+
+/// Recall of the approximate results w.r.t the exact results, i.e. the ratio of exact results that
+/// are also within the approximate results.
+pub fn recall(exact: &[usize], approximate: &[usize]) -> f32 {
+    if exact.is_empty() {
+        return 1.0;
+    }
+
+    let hits = exact.iter().filter(|idx| approximate.contains(idx)).count();
+    hits as f32 / exact.len() as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compute_top_k, range_search};
+
+    #[test]
+    fn test_quantize() {
+        let samples = EmbeddingMatrix::from_rows(&[[0.5, -1.0], [0.25, 0.0]]).unwrap();
+        let quantization = Quantization::fit(&samples, 8);
+        assert_eq!(quantization.max_level(), 127);
+        assert_eq!(quantization.quantize(samples.row(0)), vec![64, -127]);
+        assert_eq!(quantization.quantize(&[2.0, -2.0]), vec![127, -127]);
+        assert_eq!(
+            quantization.quantize_matrix(&samples).as_slice(),
+            &[64, -127, 32, 0]
+        );
+
+        assert_eq!(
+            Quantization::from_bytes(quantization.to_bytes()),
+            Some(quantization)
+        );
+        assert_eq!(Quantization::from_bytes([0; 5]), None);
+
+        assert_eq!(quantization.validate(), Ok(()));
+        assert_eq!(quantization.validate_levels(&[127, -127, 0]), Ok(()));
+        assert_eq!(
+            quantization.validate_levels(&[128]),
+            Err(VnnsError::InvalidQuantization)
+        );
+        for (bits, scale) in [
+            (12, 1.0),
+            (8, 0.0),
+            (8, -1.0),
+            (8, f32::NAN),
+            (16, f32::INFINITY),
+        ] {
+            assert_eq!(
+                Quantization { bits, scale }.validate(),
+                Err(VnnsError::InvalidQuantization)
+            );
+        }
+
+        let restored = quantization.dequantize(&quantization.quantize(samples.row(1)));
+        assert!((restored[0] - 0.25).abs() <= quantization.scale / 2.0);
+        assert_eq!(restored[1], 0.0);
+
+        // distances map back up to rounding
+        for metric in Metric::ALL {
+            let quantized = quantization.quantize_distance(0.75, metric);
+            let restored = quantization.dequantize_distance(quantized, metric);
+            assert!((restored - 0.75).abs() < 0.01, "{}", metric);
+        }
+    }
+
+    #[test]
+    fn test_compute_top_k_quantized() {
+        let samples = (0..32)
+            .map(|i| vec![(i as f32 * 0.37).sin(), (i as f32 * 0.11).cos()])
+            .collect::<Vec<_>>();
+        let samples = EmbeddingMatrix::from_rows(&samples).unwrap();
+        let query = vec![0.3, -0.2];
+
+        let quantization = Quantization::fit(&samples, 16);
+        let quantized_samples = quantization.quantize_matrix(&samples);
+        let quantized_query = quantization.quantize(&query);
+
+        for metric in Metric::ALL {
+            let exact = compute_top_k(&samples, &query, 3, metric).unwrap();
+            let approximate =
+                compute_top_k_quantized(&quantized_samples, &quantized_query, 3, metric).unwrap();
+            assert_eq!(recall(&exact, &approximate), 1.0, "{}", metric);
+        }
+    }
+
+    #[test]
+    fn test_range_search_quantized() {
+        let samples = (0..32)
+            .map(|i| vec![i as f32 / 32.0, 1.0 - i as f32 / 32.0])
+            .collect::<Vec<_>>();
+        let samples = EmbeddingMatrix::from_rows(&samples).unwrap();
+        let query = vec![0.5, 0.5];
+
+        let quantization = Quantization::fit(&samples, 16);
+        let quantized_samples = quantization.quantize_matrix(&samples);
+        let quantized_query = quantization.quantize(&query);
+
+        // radii in between the distances, such that rounding does not matter
+        for (metric, radius) in [
+            (Metric::SquaredL2, 0.05),
+            (Metric::L2, 0.2),
+            (Metric::Manhattan, 0.3),
+            (Metric::Cosine, 0.01),
+            (Metric::InnerProduct, -0.45),
+        ] {
+            let exact = range_search(&samples, &query, radius, metric).unwrap();
+            let approximate = range_search_quantized(
+                &quantized_samples,
+                &quantized_query,
+                quantization.quantize_distance(radius, metric),
+                metric,
+            )
+            .unwrap();
+            assert!(!exact.is_empty(), "{}", metric);
+            assert_eq!(exact, approximate, "{}", metric);
+        }
+    }
+
+    #[test]
+    fn test_recall() {
+        assert_eq!(recall(&[1, 2, 3, 4], &[4, 3, 7, 8]), 0.5);
+        assert_eq!(recall(&[], &[1]), 1.0);
+    }
+}
@@ -0,0 +1,130 @@
+//! Scores of the results, i.e. their distances to the query in a canonical fixed-point encoding,
+//! so that they can be committed & compared across chunks regardless of the machine.
+
+use crate::{Distance, SearchResults};
+
+/// Number of fractional bits of the score of a floating-point distance.
+pub const SCORE_FRACTION_BITS: u32 = 32;
+
+/// Distance of a sample to the query in fixed point, where a lower score is closer.
+///
+/// A floating-point distance is scaled by `2^SCORE_FRACTION_BITS` and rounded, saturating for
+/// out-of-range distances, with NaN as the farthest score. A quantized distance is kept as-is, in
+/// the units of [`crate::Quantization::quantize_distance`].
+pub type Score = i64;
+
+/// A distance that has a [`Score`], such that a closer distance never has a higher score.
+pub trait ToScore: Distance {
+    fn to_score(self) -> Score;
+}
+
+impl ToScore for f32 {
+    fn to_score(self) -> Score {
+        if self.is_nan() {
+            return Score::MAX;
+        }
+
+        // saturates for out-of-range distances
+        (self as f64 * (1u64 << SCORE_FRACTION_BITS) as f64).round() as Score
+    }
+}
+
+impl ToScore for i64 {
+    fn to_score(self) -> Score {
+        self
+    }
+}
+
+/// Maps the score of a floating-point distance back to the distance.
+pub fn score_to_distance(score: Score) -> f64 {
+    score as f64 / (1u64 << SCORE_FRACTION_BITS) as f64
+}
+
+/// Results of a search along with their scores.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Scored {
+    /// Indices of the results, in the order returned by the search.
+    pub idxs: Vec<usize>,
+    /// Score of each result.
+    pub scores: Vec<Score>,
+    /// Score of the closest sample that is not a result, or `None` if every sample is a result.
+    pub runner_up: Option<Score>,
+}
+
+impl Scored {
+    /// Scores the results with the given indices, among the samples with the given distances.
+    pub fn new<D: ToScore>(distances: &[D], idxs: Vec<usize>) -> Self {
+        let mut is_result = vec![false; distances.len()];
+        for &idx in &idxs {
+            is_result[idx] = true;
+        }
+
+        let scores = idxs.iter().map(|&idx| distances[idx].to_score()).collect();
+        let runner_up = distances
+            .iter()
+            .zip(is_result)
+            .filter(|(_, is_result)| !is_result)
+            .map(|(distance, _)| distance.to_score())
+            .min();
+
+        Self {
+            idxs,
+            scores,
+            runner_up,
+        }
+    }
+
+    /// Margin between the farthest result & the runner-up, or `None` if either is missing. For a
+    /// single nearest neighbor, this is the gap between the winner & the runner-up.
+    pub fn margin(&self) -> Option<Score> {
+        margin(&self.scores, self.runner_up)
+    }
+}
+
+impl SearchResults for Scored {
+    fn empty() -> Self {
+        Self::default()
+    }
+
+    fn map_indices(mut self, f: impl FnMut(usize) -> usize) -> Self {
+        self.idxs = self.idxs.into_iter().map(f).collect();
+        self
+    }
+}
+
+/// Margin between the farthest of the given scores & the runner-up, see [`Scored::margin`].
+pub(crate) fn margin(scores: &[Score], runner_up: Option<Score>) -> Option<Score> {
+    let farthest = scores.iter().max()?;
+    Some(runner_up?.saturating_sub(*farthest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_score() {
+        assert_eq!(1.5f32.to_score(), 3 << (SCORE_FRACTION_BITS - 1));
+        assert_eq!((-0.25f32).to_score(), -(1 << (SCORE_FRACTION_BITS - 2)));
+        assert_eq!((-0.0f32).to_score(), 0.0f32.to_score());
+        assert_eq!(f32::NAN.to_score(), Score::MAX);
+        assert_eq!(f32::INFINITY.to_score(), Score::MAX);
+        assert_eq!(f32::NEG_INFINITY.to_score(), Score::MIN);
+        assert_eq!(score_to_distance(1.5f32.to_score()), 1.5);
+        assert_eq!(7i64.to_score(), 7);
+    }
+
+    #[test]
+    fn test_scored() {
+        let scored = Scored::new(&[3i64, 1, 4, 1, 5], vec![1, 3]);
+        assert_eq!(scored.scores, vec![1, 1]);
+        assert_eq!(scored.runner_up, Some(3));
+        assert_eq!(scored.margin(), Some(2));
+
+        // every sample is a result, or there are no results
+        let scored = Scored::new(&[3i64, 1], vec![1, 0]);
+        assert_eq!((scored.runner_up, scored.margin()), (None, None));
+        let scored = Scored::new(&[3i64, 1], Vec::new());
+        assert_eq!((scored.runner_up, scored.margin()), (Some(1), None));
+    }
+}
@@ -0,0 +1,291 @@
+use std::ops::Range;
+
+/// A step of the tournament, i.e. the position of a sample within a round where it has won.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TournamentStep {
+    /// Round of the tournament, starting from 0.
+    pub round: usize,
+    /// Chunk within the round.
+    pub chunk: usize,
+    /// Index of the sample within the chunk.
+    pub local_idx: usize,
+}
+
+/// A sample that takes part in the tournament.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Contestant {
+    /// Index of the sample within the original dataset.
+    pub idx: usize,
+    /// Steps that led to this sample, one for each round it has won.
+    pub path: Vec<TournamentStep>,
+}
+
+/// Keeps track of a tournament over the samples, where the samples are processed in chunks of
+/// `batch_size` and the winners of each chunk move on to the next round. The final round has a
+/// single chunk with all remaining contestants.
+///
+/// After the first round, each chunk consists of the winners of whole chunks of the previous round,
+/// so that a chunk can prove that its samples are exactly those winners. The winners of at least
+/// two chunks must fit into a chunk for the tournament to make progress, i.e. the batch size must
+/// be at least twice the number of winners per chunk. Within a range search, every match wins, so
+/// the rounds only merge the matches until they fit into a single chunk, see
+/// [`Tournament::makes_progress`].
+///
+/// The tournament itself does not compute anything, the winners of each chunk are given to
+/// [`Tournament::advance`] so that the same bookkeeping can be used on the host & with the proofs.
+#[derive(Clone, Debug)]
+pub struct Tournament {
+    batch_size: usize,
+    round: usize,
+    finished: bool,
+    contestants: Vec<Contestant>,
+    /// Number of winners of each chunk within the previous round, empty within the first round.
+    previous_winners: Vec<usize>,
+}
+
+impl Tournament {
+    pub fn new(num_samples: usize, batch_size: usize) -> Self {
+        assert!(batch_size > 0, "batch size must be positive");
+        Self {
+            batch_size,
+            round: 0,
+            finished: false,
+            contestants: (0..num_samples)
+                .map(|idx| Contestant {
+                    idx,
+                    path: Vec::new(),
+                })
+                .collect(),
+            previous_winners: Vec::new(),
+        }
+    }
+
+    /// Current round, starting from 0.
+    pub fn round(&self) -> usize {
+        self.round
+    }
+
+    /// Whether the current round is the final one, i.e. it has a single chunk.
+    pub fn is_final_round(&self) -> bool {
+        self.contestants.len() <= self.batch_size
+    }
+
+    /// Whether the final round has been played.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Contestants of the current round, or the winners if the tournament is finished.
+    pub fn contestants(&self) -> &[Contestant] {
+        &self.contestants
+    }
+
+    /// Dataset indices of the samples within each chunk of the current round.
+    pub fn chunks(&self) -> Vec<Vec<usize>> {
+        self.chunk_bounds()
+            .into_iter()
+            .map(|(contestants, _)| {
+                self.contestants[contestants]
+                    .iter()
+                    .map(|contestant| contestant.idx)
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Chunks of the previous round whose winners make up each chunk of the current round, empty
+    /// within the first round.
+    pub fn previous_chunks(&self) -> Vec<Range<usize>> {
+        self.chunk_bounds()
+            .into_iter()
+            .map(|(_, previous)| previous)
+            .collect()
+    }
+
+    /// Whether the tournament would make progress with the given number of winners of each chunk,
+    /// i.e. whether the next round would be the final one or would have fewer chunks, so that
+    /// [`Tournament::advance`] can be called with them.
+    ///
+    /// This always holds if the batch size is at least twice the number of winners per chunk, but
+    /// not within a range search, where the matches of two chunks may not fit into one.
+    pub fn makes_progress(&self, num_winners: &[usize]) -> bool {
+        self.is_final_round()
+            || num_winners.iter().sum::<usize>() <= self.batch_size
+            || num_winners
+                .windows(2)
+                .any(|pair| pair[0] + pair[1] <= self.batch_size)
+    }
+
+    /// Moves on to the next round, with the winners of each chunk given as indices within that
+    /// chunk, in order.
+    pub fn advance(&mut self, winners: &[Vec<usize>]) {
+        assert!(!self.finished, "tournament is finished");
+
+        let chunks = self.chunk_bounds();
+        let num_chunks = chunks.len();
+        assert_eq!(
+            chunks.len(),
+            winners.len(),
+            "expected winners for each chunk"
+        );
+
+        let mut next_contestants = Vec::new();
+        for (chunk_idx, ((chunk, _), chunk_winners)) in chunks.into_iter().zip(winners).enumerate()
+        {
+            let chunk = &self.contestants[chunk];
+            for &local_idx in chunk_winners {
+                let mut contestant = chunk[local_idx].clone();
+                contestant.path.push(TournamentStep {
+                    round: self.round,
+                    chunk: chunk_idx,
+                    local_idx,
+                });
+                next_contestants.push(contestant);
+            }
+        }
+
+        // winners of the final round are kept in order, otherwise the contestants are kept in
+        // dataset order so that the ties are resolved by the lowest dataset index
+        self.finished = self.is_final_round();
+        if !self.finished {
+            next_contestants.sort_by_key(|contestant| contestant.idx);
+        }
+        self.contestants = next_contestants;
+        self.previous_winners = winners.iter().map(Vec::len).collect();
+        self.round += 1;
+
+        // the number of chunks must decrease for the tournament to terminate
+        assert!(
+            self.finished || self.chunk_bounds().len() < num_chunks,
+            "too many winners per chunk"
+        );
+    }
+
+    /// Bounds of each chunk within the current round, as ranges of the contestants & of the chunks
+    /// of the previous round.
+    fn chunk_bounds(&self) -> Vec<(Range<usize>, Range<usize>)> {
+        // the first round is split into chunks of the batch size, if there are any samples
+        if self.previous_winners.is_empty() {
+            return (0..self.contestants.len())
+                .step_by(self.batch_size)
+                .map(|start| {
+                    let end = (start + self.batch_size).min(self.contestants.len());
+                    (start..end, 0..0)
+                })
+                .collect();
+        }
+        if self.is_final_round() {
+            return vec![(0..self.contestants.len(), 0..self.previous_winners.len())];
+        }
+
+        // otherwise, the winners of as many chunks as fit into the batch size are merged
+        let mut bounds = Vec::new();
+        let (mut start, mut previous_start, mut len) = (0, 0, 0);
+        for (previous_idx, &num_winners) in self.previous_winners.iter().enumerate() {
+            if len + num_winners > self.batch_size && previous_idx > previous_start {
+                bounds.push((start..start + len, previous_start..previous_idx));
+                (start, previous_start, len) = (start + len, previous_idx, 0);
+            }
+            len += num_winners;
+        }
+        bounds.push((
+            start..start + len,
+            previous_start..self.previous_winners.len(),
+        ));
+        bounds
+    }
+
+    /// Winners of the tournament, in order.
+    pub fn into_winners(self) -> Vec<Contestant> {
+        assert!(self.finished, "tournament is not finished");
+        self.contestants
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tournament() {
+        let mut tournament = Tournament::new(7, 3);
+        assert_eq!(
+            tournament.chunks(),
+            vec![vec![0, 1, 2], vec![3, 4, 5], vec![6]]
+        );
+
+        // first round, winners are 2, 3 and 6
+        tournament.advance(&[vec![2], vec![0], vec![0]]);
+        assert!(tournament.is_final_round());
+        assert_eq!(tournament.chunks(), vec![vec![2, 3, 6]]);
+
+        // final round, winner is 6
+        tournament.advance(&[vec![2]]);
+        let winners = tournament.into_winners();
+        assert_eq!(winners.len(), 1);
+        assert_eq!(winners[0].idx, 6);
+        assert_eq!(
+            winners[0].path,
+            vec![
+                TournamentStep {
+                    round: 0,
+                    chunk: 2,
+                    local_idx: 0
+                },
+                TournamentStep {
+                    round: 1,
+                    chunk: 0,
+                    local_idx: 2
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_previous_chunks() {
+        let mut tournament = Tournament::new(10, 4);
+        assert_eq!(tournament.previous_chunks(), vec![0..0, 0..0, 0..0]);
+
+        // the winners of whole chunks are merged, as many as fit into the batch size
+        tournament.advance(&[vec![0, 1], vec![3, 2], vec![1]]);
+        assert_eq!(tournament.chunks(), vec![vec![0, 1, 6, 7], vec![9]]);
+        assert_eq!(tournament.previous_chunks(), vec![0..2, 2..3]);
+
+        tournament.advance(&[vec![3, 0], vec![0]]);
+        assert!(tournament.is_final_round());
+        assert_eq!(tournament.chunks(), vec![vec![0, 7, 9]]);
+        assert_eq!(tournament.previous_chunks(), vec![0..2]);
+
+        // a chunk without winners is merged as well
+        let mut tournament = Tournament::new(6, 2);
+        tournament.advance(&[vec![1], Vec::new(), vec![0]]);
+        assert_eq!(tournament.chunks(), vec![vec![1, 4]]);
+        assert_eq!(tournament.previous_chunks(), vec![0..3]);
+    }
+
+    #[test]
+    fn test_range_rounds() {
+        // every sample of the chunks may win, and the matches are merged until they fit
+        let mut tournament = Tournament::new(9, 3);
+        let matches = [vec![0, 2], vec![1], vec![0, 1]];
+        assert!(tournament.makes_progress(&[2, 1, 2]));
+        tournament.advance(&matches);
+        assert!(!tournament.is_final_round());
+        assert_eq!(tournament.chunks(), vec![vec![0, 2, 4], vec![6, 7]]);
+        assert_eq!(tournament.previous_chunks(), vec![0..2, 2..3]);
+
+        // the matches of both chunks would make up the next round again
+        assert!(!tournament.makes_progress(&[3, 2]));
+        assert!(tournament.makes_progress(&[2, 1]));
+        tournament.advance(&[vec![0, 2], vec![0]]);
+        assert!(tournament.is_final_round());
+        assert_eq!(tournament.chunks(), vec![vec![0, 4, 6]]);
+
+        // the matches of no two chunks fit into one, so no round could merge them
+        let tournament = Tournament::new(9, 3);
+        assert!(!tournament.makes_progress(&[3, 2, 3]));
+        let tournament = Tournament::new(6, 2);
+        assert!(!tournament.makes_progress(&[2, 2, 2]));
+        assert!(tournament.makes_progress(&[2, 0, 2]));
+    }
+}
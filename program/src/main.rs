@@ -2,39 +2,193 @@
 sp1_zkvm::entrypoint!(main);
 
 use sha2::{Digest, Sha256};
-use vnns_lib::compute_best_sample;
+use vnns_lib::encoding::query_commitment;
+use vnns_lib::{
+    compute_top_k_quantized_scored, compute_top_k_scored, link_to_dataset, link_to_previous_round,
+    range_search_quantized_scored, range_search_scored, search_filtered, Element, EmbeddingMatrix,
+    Filter, Hash, LinkedChunk, MerkleProof, Metadata, Metric, PublicOutputs, Quantization, Scored,
+    SearchMode, VnnsError, STATUS_OK,
+};
+
+/// Inputs that are the same for floating-point & quantized samples.
+struct Dataset {
+    samples_bytes: Vec<u8>,
+    /// Metadata of each sample, or `None` if none of them has metadata.
+    metadata: Result<Option<Vec<Metadata>>, VnnsError>,
+    /// Hash of the payload of each sample, so that the results identify the items themselves.
+    payloads: Vec<Hash>,
+    filter: Result<Option<Filter>, VnnsError>,
+    /// Inclusion proof of each sample, within the first round.
+    proofs: Vec<MerkleProof>,
+    /// Public values of the previous round whose winners are the samples, empty within the first
+    /// round.
+    previous: Vec<Vec<u8>>,
+}
+
+impl Dataset {
+    /// Decodes the samples and links them to the dataset, either by their inclusion proofs or
+    /// through the previous round, then searches all samples or only those whose metadata passes
+    /// the filter if there is one.
+    ///
+    /// Returns the linked samples, along with the results & their scores among the samples.
+    fn search<T: Element>(
+        &self,
+        outputs: &PublicOutputs,
+        search: impl FnOnce(&EmbeddingMatrix<T>) -> Result<Scored, VnnsError>,
+    ) -> Result<(LinkedChunk, Scored), VnnsError> {
+        let samples = EmbeddingMatrix::<T>::from_bytes(&self.samples_bytes)?;
+        let metadata = match self.metadata.clone()? {
+            Some(metadata) => metadata,
+            None => vec![Metadata::default(); samples.len()],
+        };
+        let linked = if self.previous.is_empty() {
+            link_to_dataset(&samples, &metadata, &self.payloads, outputs, &self.proofs)?
+        } else {
+            link_to_previous_round(&samples, &metadata, &self.payloads, outputs, &self.previous)?
+        };
+
+        // a later round has no samples if no sample has passed the filter
+        let scored = if linked.samples.is_empty() && !self.previous.is_empty() {
+            Scored::default()
+        } else {
+            match self.filter.clone()? {
+                Some(filter) => search_filtered(&samples, &metadata, &filter, search)?,
+                None => search(&samples)?,
+            }
+        };
+        Ok((linked, scored))
+    }
+}
 
 pub fn main() {
-    let samples = sp1_zkvm::io::read::<Vec<Vec<f32>>>();
-    let query = sp1_zkvm::io::read::<Vec<f32>>();
-
-    // compute similarity and return index
-    let idx = compute_best_sample(&samples, &query);
-
-    // commit to output
-    let output_bytes = samples[idx as usize]
-        .iter()
-        .flat_map(|f| f.to_ne_bytes())
-        .collect::<Vec<_>>();
-    let output_commit = Sha256::digest(&output_bytes);
-
-    // commit to query
-    let query_bytes = query
-        .into_iter()
-        .flat_map(|f| f.to_ne_bytes())
-        .collect::<Vec<_>>();
-    let query_commit = Sha256::digest(&query_bytes);
-
-    // commit to samples
-    let samples_bytes = samples
-        .into_iter()
-        .flatten()
-        .flat_map(|f| f.to_ne_bytes())
-        .collect::<Vec<_>>();
-    let samples_commit = Sha256::digest(&samples_bytes);
-
-    sp1_zkvm::io::commit_slice(&(idx as u32).to_ne_bytes()); // 8 byte (u32)
-    sp1_zkvm::io::commit_slice(&query_commit); // 32 byte
-    sp1_zkvm::io::commit_slice(&samples_commit); // 32 byte
-    sp1_zkvm::io::commit_slice(&output_commit); // 32 byte
+    let metric_id = sp1_zkvm::io::read::<u8>();
+    let mode = sp1_zkvm::io::read::<SearchMode>();
+    let quantization = sp1_zkvm::io::read::<Option<Quantization>>();
+    // unsupported quantization parameters are rejected with no quantization committed
+    let quantization_check = quantization.map_or(Ok(()), |quantization| quantization.validate());
+    let samples_bytes = sp1_zkvm::io::read_vec();
+
+    // the filter & the metadata of each sample, either is empty if there is none, along with the
+    // payload hash of each sample
+    let filter_bytes = sp1_zkvm::io::read_vec();
+    let metadata_bytes = sp1_zkvm::io::read_vec();
+    let payloads = sp1_zkvm::io::read::<Vec<Hash>>();
+
+    // root & size of the dataset, which the root commits to, & verifying key of this program, the
+    // same for every chunk
+    let root = sp1_zkvm::io::read::<Hash>();
+    let dataset_size = sp1_zkvm::io::read::<usize>();
+    let vkey = sp1_zkvm::io::read::<[u32; 8]>();
+
+    // public values of the previous round, or the inclusion proof of each sample within the first
+    let previous = sp1_zkvm::io::read::<Vec<Vec<u8>>>();
+    let proofs = sp1_zkvm::io::read::<Vec<MerkleProof>>();
+
+    // the salt stays private, so that the query commitment hides the query if there is one
+    let salt = sp1_zkvm::io::read::<Option<Hash>>();
+
+    // the proofs of the previous round must be valid, otherwise there is no proof at all
+    for public_values in &previous {
+        let public_values_digest = Sha256::digest(public_values);
+        sp1_zkvm::lib::verify::verify_sp1_proof(&vkey, &public_values_digest.into());
+    }
+
+    let dataset = Dataset {
+        samples_bytes,
+        metadata: if metadata_bytes.is_empty() {
+            Ok(None)
+        } else {
+            Metadata::decode_all(&metadata_bytes).map(Some)
+        },
+        payloads,
+        filter: if filter_bytes.is_empty() {
+            Ok(None)
+        } else {
+            Filter::from_bytes(&filter_bytes).map(Some)
+        },
+        proofs,
+        previous,
+    };
+
+    // the scale is committed as well, as it affects the radius of a quantized search, and the
+    // filter is committed unless it is malformed, in which case the status says so
+    let mut outputs = PublicOutputs {
+        status: STATUS_OK,
+        query_commitment: [0u8; 32],
+        dataset_root: root,
+        dataset_size,
+        program_vkey: vkey,
+        covered: 0..0,
+        metric_id,
+        mode,
+        quantization: quantization.filter(|_| quantization_check.is_ok()),
+        filter: dataset.filter.clone().ok().flatten(),
+        results: Vec::new(),
+        scores: Vec::new(),
+        runner_up: None,
+    };
+
+    // compute similarity and return the k best indices in order, or all indices within the
+    // radius, along with the hash & the score of each winning sample
+    let metric = Metric::try_from(metric_id);
+    let result = match quantization {
+        None => {
+            let query = sp1_zkvm::io::read::<Vec<f32>>();
+            outputs.query_commitment = query_commitment(&query, salt.as_ref());
+            metric.and_then(|metric| {
+                dataset.search(&outputs, |samples| match mode {
+                    SearchMode::TopK { k } => {
+                        compute_top_k_scored(samples, &query, k as usize, metric)
+                    }
+                    SearchMode::Range { radius } => {
+                        range_search_scored(samples, &query, radius, metric)
+                    }
+                })
+            })
+        }
+        Some(quantization) => {
+            // integer arithmetic only, the scale is only needed to quantize the radius
+            let query = sp1_zkvm::io::read::<Vec<i16>>();
+            outputs.query_commitment = query_commitment(&query, salt.as_ref());
+            quantization_check
+                .and_then(|_| quantization.validate_levels(&query))
+                .and(metric)
+                .and_then(|metric| {
+                    dataset.search(&outputs, |samples| {
+                        quantization.validate_levels(samples.as_slice())?;
+                        match mode {
+                            SearchMode::TopK { k } => {
+                                compute_top_k_quantized_scored(samples, &query, k as usize, metric)
+                            }
+                            SearchMode::Range { radius } if radius.is_nan() => {
+                                Err(VnnsError::InvalidRadius)
+                            }
+                            SearchMode::Range { radius } => {
+                                let radius = quantization.quantize_distance(radius, metric);
+                                range_search_quantized_scored(samples, &query, radius, metric)
+                            }
+                        }
+                    })
+                })
+        }
+    };
+
+    // an invalid input is committed as a status code with no results, instead of aborting
+    match result {
+        Ok((linked, scored)) => {
+            outputs.covered = linked.covered;
+            outputs.results = scored.idxs.iter().map(|&idx| linked.samples[idx]).collect();
+            outputs.scores = scored.scores;
+            // the closest sample that is not a result, including those of the previous rounds
+            outputs.runner_up = linked.runner_up.into_iter().chain(scored.runner_up).min();
+        }
+        Err(err) => {
+            // quantized values out of the range of the bits leave the quantization unusable too
+            if err == VnnsError::InvalidQuantization {
+                outputs.quantization = None;
+            }
+            outputs.status = err.code();
+        }
+    }
+    sp1_zkvm::io::commit_slice(&outputs.encode());
 }
@@ -3,44 +3,683 @@
 //!
 //! You can run this script using the following command:
 //! ```shell
-//! RUST_LOG=info cargo run --release -- --execute
+//! RUST_LOG=info cargo run --release -- execute
 //! ```
 //! or
 //! ```shell
-//! RUST_LOG=info cargo run --release -- --prove
+//! RUST_LOG=info cargo run --release -- prove
 //! ```
 
-use clap::Parser;
-use sp1_sdk::{
-    HashableKey, ProverClient, SP1Proof, SP1ProofWithPublicValues, SP1Stdin, SP1VerifyingKey,
+use clap::{Args, Parser, Subcommand};
+use serde::{Deserialize, Serialize};
+use sp1_sdk::{HashableKey, ProverClient, SP1Proof, SP1ProofWithPublicValues, SP1Stdin};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Mutex};
+use vnns_embedder::{
+    data_hash, dataset_tree, embedding_matrix, quantized_tree, Data, EmbeddedData, Inclusion,
+    QuantizedIndex, ResultReceipt, DEFAULT_MODEL,
 };
-use std::path::PathBuf;
-use vnns_embedder::{Data, EmbeddedData};
+use vnns_lib::encoding::query_commitment;
+use vnns_lib::{
+    AggregatedOutputs, Condition, Contestant, Element, EmbeddingMatrix, Filter, Hash, MerkleProof,
+    MerkleTree, Metadata, Metric, PublicOutputs, Quantization, Score, Scored, SearchMode,
+    Tournament, VnnsError, STATUS_OK,
+};
+
+mod manifest;
+use manifest::{
+    encode_vkey, file_name, proof_path, public_values_path, AggregationEntry, Manifest, ProofEntry,
+};
+mod verify;
 
 pub const PROGRAM_ELF: &[u8] = include_bytes!("../../elf/riscv32im-succinct-vnns-elf");
 pub const AGGREGATOR_ELF: &[u8] = include_bytes!("../../elf/riscv32im-succinct-aggregator-elf");
 
-struct AggregationInput {
-    pub proof: SP1ProofWithPublicValues,
-    pub vk: SP1VerifyingKey,
+/// Prints the public values committed by the VNNS program to console.
+fn print_outputs(outputs: &PublicOutputs) {
+    println!(
+        "Query Commitment: {}",
+        hex::encode(outputs.query_commitment)
+    );
+    println!("Dataset Root: {}", hex::encode(outputs.dataset_root));
+    println!("Dataset Size: {}", outputs.dataset_size);
+    println!(
+        "Covered: {}..{}",
+        outputs.covered.start, outputs.covered.end
+    );
+    match Metric::try_from(outputs.metric_id) {
+        Ok(metric) => println!("Metric: {}", metric),
+        Err(err) => println!("Metric: {}", err),
+    }
+    println!("Mode: {}", outputs.mode);
+    if let Some(quantization) = outputs.quantization {
+        println!(
+            "Quantization: {} bits, scale {}",
+            quantization.bits, quantization.scale
+        );
+    }
+    if let Some(filter) = &outputs.filter {
+        println!("Filter: {}", filter);
+    }
+    if outputs.is_rejected() {
+        println!(
+            "Input rejected: {} (status {})",
+            VnnsError::describe_code(outputs.status),
+            outputs.status
+        );
+    }
+    let label = match outputs.mode {
+        SearchMode::Range { .. } => "Index within radius",
+        SearchMode::TopK { .. } => "Closest index",
+    };
+    for (rank, ((idx, output_commitment), score)) in
+        outputs.results.iter().zip(&outputs.scores).enumerate()
+    {
+        println!(
+            "#{} {}: {}, Score: {}, Output Commitment: {}",
+            rank + 1,
+            label,
+            idx,
+            format_score(outputs, *score),
+            hex::encode(output_commitment)
+        );
+    }
+    if let Some(runner_up) = outputs.runner_up {
+        println!("Runner-up Score: {}", format_score(outputs, runner_up));
+    }
+    if let Some(margin) = outputs.margin() {
+        println!("Margin: {}", format_score(outputs, margin));
+    }
+}
+
+/// Formats a score as the distance it stands for, along with the committed fixed-point value.
+fn format_score(outputs: &PublicOutputs, score: Score) -> String {
+    match outputs.distance(score) {
+        Some(distance) => format!("{} ({})", distance, score),
+        None => score.to_string(),
+    }
+}
+
+/// Prints the public values committed by the aggregation program to console.
+fn print_aggregated(aggregated: &AggregatedOutputs) {
+    if aggregated.is_rejected() {
+        println!(
+            "Aggregation rejected: {} (status {})",
+            VnnsError::describe_code(aggregated.status),
+            aggregated.status
+        );
+    }
+    println!(
+        "Program Verifying Key: {}",
+        encode_vkey(aggregated.program_vkey)
+    );
+    println!(
+        "Aggregator Verifying Key: {}",
+        encode_vkey(aggregated.aggregator_vkey)
+    );
+    println!("Dataset Root: {}", hex::encode(aggregated.dataset_root));
+    println!("Dataset Size: {}", aggregated.dataset_size);
+    println!(
+        "Query Commitment: {}",
+        hex::encode(aggregated.query_commitment)
+    );
+    println!("Number of Results: {}", aggregated.num_results);
+    if let Some((idx, output_commitment, score)) = aggregated.winner {
+        println!(
+            "Winner: {}, Score: {}, Output Commitment: {}",
+            idx,
+            score,
+            hex::encode(output_commitment)
+        );
+    }
+    if let Some(margin) = aggregated.margin {
+        println!("Margin: {}", margin);
+    }
+    println!(
+        "Final Public Values Digest: {}",
+        hex::encode(aggregated.final_digest)
+    );
+    println!("Proofs Root: {}", hex::encode(aggregated.proofs_root));
+}
+
+/// Proves the chunks `0..count` of a round with up to `jobs` workers at once, which share the
+/// client & the proving key through `prove`.
+///
+/// Each result is passed to `report` in chunk order, as soon as all previous chunks are done, so
+/// that the progress & the returned proofs are in the same order as if the chunks were proven one
+/// after another. No further chunks are started once a chunk has rejected its input, and the
+/// results end with the first rejected chunk.
+fn prove_chunks<P: Send>(
+    jobs: usize,
+    count: usize,
+    prove: impl Fn(usize) -> (P, PublicOutputs) + Sync,
+    mut report: impl FnMut(usize, &PublicOutputs),
+) -> Vec<(P, PublicOutputs)> {
+    let next = AtomicUsize::new(0);
+    let rejected = AtomicBool::new(false);
+    let mut results = Vec::with_capacity(count);
+    std::thread::scope(|scope| {
+        let (sender, receiver) = mpsc::channel();
+        for _ in 0..jobs.min(count) {
+            let sender = sender.clone();
+            let (next, rejected, prove) = (&next, &rejected, &prove);
+            // chunks are taken in order, so every chunk before a rejected one is proven
+            scope.spawn(move || {
+                while !rejected.load(Ordering::SeqCst) {
+                    let idx = next.fetch_add(1, Ordering::SeqCst);
+                    if idx >= count {
+                        break;
+                    }
+                    let (proof, output) = prove(idx);
+                    if output.is_rejected() {
+                        rejected.store(true, Ordering::SeqCst);
+                    }
+                    if sender.send((idx, proof, output)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        // buffer the chunks that finish early until all previous ones are done
+        let mut pending = BTreeMap::new();
+        for (idx, proof, output) in receiver {
+            pending.insert(idx, (proof, output));
+            while !results
+                .last()
+                .is_some_and(|(_, output): &(_, PublicOutputs)| output.is_rejected())
+            {
+                let Some((proof, output)) = pending.remove(&results.len()) else {
+                    break;
+                };
+                report(results.len(), &output);
+                results.push((proof, output));
+            }
+        }
+    });
+    results
+}
+
+/// Range of the dataset that the proof of a chunk covers, given the proofs of the previous chunks
+/// whose winners it consists of, or none within the first round. Returns `None` for an empty chunk
+/// within the first round, or if the public values of a previous proof are malformed.
+fn covered_range(chunk: &[usize], previous: &[SP1ProofWithPublicValues]) -> Option<Range<usize>> {
+    let covered = |proof: &SP1ProofWithPublicValues| {
+        PublicOutputs::decode(proof.public_values.as_slice())
+            .ok()
+            .map(|outputs| outputs.covered)
+    };
+    match previous {
+        [] => Some(*chunk.first()?..*chunk.last()? + 1),
+        [first, .., last] => Some(covered(first)?.start..covered(last)?.end),
+        [single] => covered(single),
+    }
+}
+
+/// Whether a saved proof with the given public values is a successful proof of the given chunk of
+/// the search, which covers the given range, see [`covered_range`], so that it can be reused on
+/// resume once the proof itself verifies.
+fn is_reusable(
+    output: &PublicOutputs,
+    search: &PublicOutputs,
+    chunk: &[usize],
+    covered: Option<Range<usize>>,
+) -> bool {
+    !output.is_rejected()
+        && output.is_same_search(search)
+        && Some(&output.covered) == covered.as_ref()
+        && output.indices().iter().all(|idx| chunk.contains(idx))
+}
+
+/// Writes a file through a temporary one, so that an interrupted run never leaves it half-written.
+fn write_file(path: &Path, bytes: &[u8]) {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    std::fs::write(&tmp_path, bytes).expect("failed to write file");
+    std::fs::rename(&tmp_path, path).expect("failed to write file");
+}
+
+/// Saves a proof along with its public values, under its number within the run.
+fn save_proof(path: &Path, number: usize, proof: &SP1ProofWithPublicValues) {
+    write_file(
+        &public_values_path(path, number),
+        proof.public_values.as_slice(),
+    );
+    write_file(
+        &proof_path(path, number),
+        &bincode::serialize(proof).expect("failed to serialize proof"),
+    );
+}
+
+/// Loads the proof with the given number within the run, or `None` if it is missing or malformed.
+fn load_proof(path: &Path, number: usize) -> Option<SP1ProofWithPublicValues> {
+    let bytes = std::fs::read(proof_path(path, number)).ok()?;
+    bincode::deserialize(&bytes).ok()
+}
+
+/// Positions of the given dataset indices within a chunk.
+fn local_indices(chunk: &[usize], idxs: &[usize]) -> Vec<usize> {
+    idxs.iter()
+        .map(|idx| {
+            chunk
+                .iter()
+                .position(|chunk_idx| chunk_idx == idx)
+                .expect("result is not within the chunk")
+        })
+        .collect()
+}
+
+/// Samples & query to be searched, either as-is or quantized.
+enum Embeddings {
+    Float {
+        samples: EmbeddingMatrix,
+        query: Vec<f32>,
+    },
+    Quantized {
+        quantization: Quantization,
+        samples: EmbeddingMatrix<i16>,
+        query: Vec<i16>,
+    },
+}
+
+impl Embeddings {
+    /// Searches among the samples at the given indices on the host, the same way as the program.
+    pub fn search(
+        &self,
+        idxs: &[usize],
+        metadata: &[Metadata],
+        params: &SearchParams,
+    ) -> Result<Scored, VnnsError> {
+        match self {
+            Embeddings::Float { samples, query } => {
+                params.search_with_filter(&samples.select(idxs), idxs, metadata, |samples| {
+                    match params.mode {
+                        SearchMode::TopK { k } => vnns_lib::compute_top_k_scored(
+                            samples,
+                            query,
+                            k as usize,
+                            params.metric,
+                        ),
+                        SearchMode::Range { radius } => {
+                            vnns_lib::range_search_scored(samples, query, radius, params.metric)
+                        }
+                    }
+                })
+            }
+            Embeddings::Quantized {
+                quantization,
+                samples,
+                query,
+            } => params.search_with_filter(&samples.select(idxs), idxs, metadata, |samples| {
+                match params.mode {
+                    SearchMode::TopK { k } => vnns_lib::compute_top_k_quantized_scored(
+                        samples,
+                        query,
+                        k as usize,
+                        params.metric,
+                    ),
+                    SearchMode::Range { radius } if radius.is_nan() => {
+                        Err(VnnsError::InvalidRadius)
+                    }
+                    SearchMode::Range { radius } => vnns_lib::range_search_quantized_scored(
+                        samples,
+                        query,
+                        quantization.quantize_distance(radius, params.metric),
+                        params.metric,
+                    ),
+                }
+            }),
+        }
+    }
+}
+
+/// Opening of a salted query commitment, which proves later which query was used.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct QueryOpening {
+    /// Hex encoded query commitment, as committed by every proof.
+    commitment: String,
+    /// Hex encoded secret salt.
+    salt: String,
+    /// Query vector.
+    query: Vec<f32>,
+    /// Quantized query vector, which is the committed one if the search is quantized.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    quantized_query: Option<Vec<i16>>,
+}
+
+impl QueryOpening {
+    /// Loads the salt of a saved opening, or `None` if there is none.
+    fn load_salt(path: &Path) -> Option<Hash> {
+        let bytes = std::fs::read(path).ok()?;
+        let opening = serde_json::from_slice::<QueryOpening>(&bytes).ok()?;
+        hex::decode(opening.salt).ok()?.try_into().ok()
+    }
+}
+
+/// State of a proving run, saved with the extension `.run.json` next to the proofs, so that an
+/// interrupted run can be resumed.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct RunState {
+    /// Hex encoded verifying key digest of the VNNS program.
+    program_vkey: String,
+    /// Hex encoded Merkle root of the dataset, in the searched representation.
+    dataset_root: String,
+    /// Hex encoded query commitment.
+    query_commitment: String,
+    metric: String,
+    mode: SearchMode,
+    batch_size: usize,
+    filter: Option<String>,
+    /// Numbers of the proofs that have been saved so far.
+    saved: BTreeSet<usize>,
+}
+
+impl RunState {
+    /// Whether both runs prove the same search in the same chunks, regardless of their progress.
+    fn is_same_run(&self, other: &Self) -> bool {
+        let without_progress = |state: &Self| Self {
+            saved: BTreeSet::new(),
+            ..state.clone()
+        };
+        without_progress(self) == without_progress(other)
+    }
+
+    fn save(&self, path: &Path) {
+        write_file(
+            path,
+            &serde_json::to_vec_pretty(self).expect("failed to serialize"),
+        );
+    }
+}
+
+/// Dataset & query to be searched.
+struct SearchData {
+    pub embeddings: Embeddings,
+    /// Metadata of each sample.
+    pub metadata: Vec<Metadata>,
+    /// Hash of the raw data of each sample, see [`data_hash`].
+    pub payloads: Vec<Hash>,
+    /// Merkle tree over the hashes of the samples, in the same representation as the embeddings.
+    pub tree: MerkleTree,
+    /// Secret salt of the query commitment, if the query is to be hidden.
+    pub salt: Option<Hash>,
+}
+
+impl SearchData {
+    /// Prepares the given index & query of the data file to be searched, over its quantized index
+    /// if requested.
+    pub fn load(
+        path: &Path,
+        quantized: bool,
+        samples_data: &[EmbeddedData<Data>],
+        query: &[f32],
+        salt: Option<Hash>,
+    ) -> Self {
+        // Read quantized samples from file, if requested
+        let (embeddings, tree) = if quantized {
+            let quantized_bytes =
+                std::fs::read(path.with_extension("qindex.json")).expect("failed to read the file");
+            let quantized_index: QuantizedIndex =
+                serde_json::from_slice(&quantized_bytes).expect("failed to parse JSON");
+            let quantization = quantized_index.quantization;
+            let tree = quantized_tree(&quantization, &quantized_index.embeddings, samples_data);
+            assert_eq!(hex::encode(tree.root()), quantized_index.root);
+
+            // query is quantized with the parameters of the dataset
+            let embeddings = Embeddings::Quantized {
+                quantization,
+                samples: quantized_index.embeddings,
+                query: quantization.quantize(query),
+            };
+            (embeddings, tree)
+        } else {
+            let embeddings = Embeddings::Float {
+                samples: embedding_matrix(samples_data),
+                query: query.to_vec(),
+            };
+            (embeddings, dataset_tree(samples_data))
+        };
+
+        Self {
+            embeddings,
+            metadata: samples_data
+                .iter()
+                .map(|data| data.metadata.clone())
+                .collect(),
+            payloads: samples_data
+                .iter()
+                .map(|data| data_hash(&data.data))
+                .collect(),
+            tree,
+            salt,
+        }
+    }
+
+    /// Number of samples.
+    pub fn len(&self) -> usize {
+        self.metadata.len()
+    }
+
+    /// Writes the program input to search the samples at the given indices, where the samples are
+    /// written as a single buffer to be read with `read_vec`.
+    ///
+    /// Within the first round, the samples are written along with their inclusion proofs. Within a
+    /// later round, they are the winners of the previous chunks with the given public values, whose
+    /// proofs must be written to `stdin` as well.
+    pub fn write_input(
+        &self,
+        stdin: &mut SP1Stdin,
+        idxs: &[usize],
+        params: &SearchParams,
+        vkey: [u32; 8],
+        previous: &[Vec<u8>],
+    ) {
+        stdin.write(&u8::from(params.metric));
+        stdin.write(&params.mode);
+        match &self.embeddings {
+            Embeddings::Float { samples, .. } => {
+                stdin.write::<Option<Quantization>>(&None);
+                stdin.write_vec(samples.select(idxs).to_bytes());
+            }
+            Embeddings::Quantized {
+                quantization,
+                samples,
+                ..
+            } => {
+                stdin.write(&Some(*quantization));
+                stdin.write_vec(samples.select(idxs).to_bytes());
+            }
+        }
+
+        // filter & metadata are left empty if there are none
+        stdin.write_vec(
+            params
+                .filter
+                .as_ref()
+                .map(Filter::to_bytes)
+                .unwrap_or_default(),
+        );
+        let metadata = idxs
+            .iter()
+            .map(|&idx| self.metadata[idx].clone())
+            .collect::<Vec<_>>();
+        if metadata.iter().all(Metadata::is_empty) {
+            stdin.write_vec(Vec::new());
+        } else {
+            stdin.write_vec(Metadata::encode_all(&metadata));
+        }
+        stdin.write(
+            &idxs
+                .iter()
+                .map(|&idx| self.payloads[idx])
+                .collect::<Vec<_>>(),
+        );
+
+        stdin.write(&self.tree.root());
+        stdin.write(&self.len());
+        stdin.write(&vkey);
+        stdin.write(&previous.to_vec());
+        if previous.is_empty() {
+            stdin.write(
+                &idxs
+                    .iter()
+                    .map(|&idx| self.tree.proof(idx))
+                    .collect::<Vec<_>>(),
+            );
+        } else {
+            stdin.write(&Vec::<MerkleProof>::new());
+        }
+
+        stdin.write(&self.salt);
+        match &self.embeddings {
+            Embeddings::Float { query, .. } => stdin.write(query),
+            Embeddings::Quantized { query, .. } => stdin.write(query),
+        }
+    }
+
+    /// Receipt of the result at the given dataset index, which opens the item by its inclusion
+    /// proof.
+    pub fn receipt(&self, idx: usize, item: &EmbeddedData<Data>) -> ResultReceipt<Data> {
+        ResultReceipt {
+            idx,
+            item: item.clone(),
+            quantized_embeddings: match &self.embeddings {
+                Embeddings::Float { .. } => None,
+                Embeddings::Quantized { samples, .. } => Some(samples.row(idx).to_vec()),
+            },
+            inclusion: Inclusion {
+                root: hex::encode(self.tree.root()),
+                hash: hex::encode(self.tree.leaf(idx)),
+                proof: self.tree.proof(idx),
+            },
+        }
+    }
+
+    /// Public values that every successful chunk of the search commits to, apart from its covered
+    /// range & its results, see [`PublicOutputs::is_same_search`].
+    pub fn search_outputs(&self, params: &SearchParams, vkey: [u32; 8]) -> PublicOutputs {
+        PublicOutputs {
+            status: STATUS_OK,
+            query_commitment: self.query_commitment(),
+            dataset_root: self.tree.root(),
+            dataset_size: self.len(),
+            program_vkey: vkey,
+            covered: 0..0,
+            metric_id: params.metric.into(),
+            mode: params.mode,
+            quantization: match &self.embeddings {
+                Embeddings::Float { .. } => None,
+                Embeddings::Quantized { quantization, .. } => Some(*quantization),
+            },
+            filter: params.filter.clone(),
+            results: Vec::new(),
+            scores: Vec::new(),
+            runner_up: None,
+        }
+    }
+
+    /// Query commitment, as committed by the program.
+    pub fn query_commitment(&self) -> Hash {
+        match &self.embeddings {
+            Embeddings::Float { query, .. } => query_commitment(query, self.salt.as_ref()),
+            Embeddings::Quantized { query, .. } => query_commitment(query, self.salt.as_ref()),
+        }
+    }
+
+    /// Searches among the samples at the given indices on the host, the same way as the program.
+    pub fn search(&self, idxs: &[usize], params: &SearchParams) -> Result<Scored, VnnsError> {
+        self.embeddings.search(idxs, &self.metadata, params)
+    }
+}
+
+/// Parameters of the search, the same for every chunk.
+struct SearchParams {
+    pub metric: Metric,
+    pub mode: SearchMode,
+    pub filter: Option<Filter>,
+}
+
+impl SearchParams {
+    /// Searches the given samples, or only those that pass the filter if there is one, where the
+    /// samples are at the given indices within the dataset.
+    fn search_with_filter<T: Element>(
+        &self,
+        samples: &EmbeddingMatrix<T>,
+        idxs: &[usize],
+        metadata: &[Metadata],
+        search: impl FnOnce(&EmbeddingMatrix<T>) -> Result<Scored, VnnsError>,
+    ) -> Result<Scored, VnnsError> {
+        match &self.filter {
+            Some(filter) => {
+                let metadata = idxs
+                    .iter()
+                    .map(|&idx| metadata[idx].clone())
+                    .collect::<Vec<_>>();
+                vnns_lib::search_filtered(samples, &metadata, filter, search)
+            }
+            None => search(samples),
+        }
+    }
 }
 
 /// The arguments for the command.
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
-struct Args {
-    /// Simulate the execution of the program, without a proof.
-    #[clap(long)]
-    execute: bool,
+struct Cli {
+    #[clap(subcommand)]
+    command: Command,
+}
 
-    /// Generate batches of proofs.
-    #[clap(long)]
-    prove: bool,
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Simulate the execution of the program over the whole dataset, without a proof.
+    Execute {
+        #[clap(flatten)]
+        search: SearchArgs,
+    },
+    /// Generate the proofs of every chunk, round after round, and describe them in a manifest.
+    Prove {
+        #[clap(flatten)]
+        search: SearchArgs,
+        #[clap(flatten)]
+        options: ProveArgs,
+    },
+    /// Aggregate the saved proofs of a run into one final proof, without proving them again.
+    Aggregate {
+        /// Path to the manifest of the run.
+        #[clap(long)]
+        manifest: PathBuf,
 
-    /// Aggregate the proofs to create only one final proof.
-    #[clap(long)]
-    aggregate: bool,
+        /// Aggregate by a tree of aggregators, each of which verifies up to this many proofs, a
+        /// power of two. All proofs are aggregated at once by default.
+        #[clap(long)]
+        fan_out: Option<usize>,
+    },
+    /// Verify the saved proofs of a run offline, along with its aggregation if any.
+    Verify {
+        /// Path to the manifest of the run.
+        #[clap(long)]
+        manifest: PathBuf,
+    },
+    /// Print the public values of the saved proofs of a run.
+    Inspect {
+        /// Path to the manifest of the run.
+        #[clap(long)]
+        manifest: PathBuf,
+
+        /// Only print the proof with this number.
+        #[clap(short, long)]
+        number: Option<usize>,
+    },
+}
 
+/// Options of a search, shared by execution & proving.
+#[derive(Args, Debug)]
+struct SearchArgs {
     /// Path to the data file.
     #[clap(short, long, default_value = "../data/foods-smol.json")]
     path: PathBuf,
@@ -48,237 +687,961 @@ struct Args {
     /// Number of samples to be taken for each batch.
     #[clap(long, default_value = "4")]
     batch_size: usize,
+
+    /// Distance metric to be used within the search.
+    #[clap(long, default_value = "squared-l2")]
+    metric: Metric,
+
+    /// Number of nearest neighbors to be returned.
+    #[clap(short, long, default_value = "1")]
+    k: u32,
+
+    /// Return all samples within this distance of the query instead of the nearest neighbors.
+    #[clap(short, long)]
+    radius: Option<f32>,
+
+    /// Only search the samples whose metadata satisfies this condition, e.g. `category=fruit` or
+    /// `price=100..500`. Can be given multiple times, in which case all conditions must hold.
+    #[clap(short, long)]
+    filter: Vec<Condition>,
+
+    /// Search over the quantized index with integer arithmetic only.
+    #[clap(long)]
+    quantized: bool,
+}
+
+/// Options that only apply to proving.
+#[derive(Args, Debug)]
+struct ProveArgs {
+    /// Aggregate the proofs to create only one final proof.
+    #[clap(long)]
+    aggregate: bool,
+
+    /// Aggregate by a tree of aggregators, each of which verifies up to this many proofs, a power
+    /// of two. All proofs are aggregated at once by default.
+    #[clap(long, requires = "aggregate")]
+    fan_out: Option<usize>,
+
+    /// Number of chunks of a round to be proven at once.
+    #[clap(short, long, default_value = "1")]
+    jobs: usize,
+
+    /// Hide the query behind a salted commitment, whose opening is saved next to the proofs.
+    #[clap(long)]
+    salted: bool,
+
+    /// Keep the dataset private, saving a receipt that opens the results instead of sharing the
+    /// index.
+    #[clap(long)]
+    private: bool,
+
+    /// Resume an interrupted proving run, reusing every saved proof that still verifies.
+    #[clap(long)]
+    resume: bool,
+
+    /// Embedding model of the index & the query, which is recorded within the manifest.
+    #[clap(short, long, default_value = DEFAULT_MODEL)]
+    model: String,
+}
+
+impl SearchArgs {
+    /// Checks the options that depend on each other.
+    fn validate(&self) -> Result<(), String> {
+        if self.batch_size == 0 {
+            return Err("Batch size must be positive".to_string());
+        }
+        if self.radius.is_none() && self.k == 0 {
+            return Err("k must be positive".to_string());
+        }
+        if self.radius.is_none() && self.batch_size < 2 * self.k as usize {
+            return Err("Batch size must be at least twice k".to_string());
+        }
+        Ok(())
+    }
+
+    fn params(&self) -> SearchParams {
+        SearchParams {
+            metric: self.metric,
+            mode: match self.radius {
+                Some(radius) => SearchMode::Range { radius },
+                None => SearchMode::TopK { k: self.k },
+            },
+            // filter over the metadata of each sample, if requested
+            filter: (!self.filter.is_empty()).then(|| Filter::new(self.filter.clone())),
+        }
+    }
 }
 
-enum ExecutionType {
-    Execute,
-    Prove,
+impl ProveArgs {
+    fn validate(&self) -> Result<(), String> {
+        if self.jobs == 0 {
+            return Err("Number of jobs must be positive".to_string());
+        }
+        validate_fan_out(self.fan_out)
+    }
+}
+
+/// Checks that the fan-out of an aggregation tree, if any, is a power of two of at least two, so
+/// that each aggregator is a subtree of the proofs tree.
+fn validate_fan_out(fan_out: Option<usize>) -> Result<(), String> {
+    match fan_out {
+        Some(fan_out) if fan_out < 2 || !fan_out.is_power_of_two() => {
+            Err("Fan-out must be a power of two of at least 2".to_string())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Exits with the given error, if any.
+fn exit_on_error<T>(result: Result<T, String>) -> T {
+    result.unwrap_or_else(|err| {
+        eprintln!("Error: {}", err);
+        std::process::exit(1);
+    })
 }
 
 fn main() {
     sp1_sdk::utils::setup_logger();
-    let args = Args::parse();
+    let cli = Cli::parse();
 
-    ////////// Parse execution type.
-    if args.execute == args.prove {
-        eprintln!("Error: You must specify either --execute or --prove");
-        std::process::exit(1);
+    match &cli.command {
+        Command::Execute { search } => {
+            exit_on_error(search.validate());
+            execute(&ProverClient::new(), search);
+        }
+        Command::Prove { search, options } => {
+            exit_on_error(search.validate().and_then(|_| options.validate()));
+            prove(&ProverClient::new(), search, options);
+        }
+        Command::Aggregate { manifest, fan_out } => {
+            exit_on_error(validate_fan_out(*fan_out));
+            exit_on_error(aggregate(&ProverClient::new(), manifest, *fan_out));
+        }
+        Command::Verify { manifest } => {
+            println!("Verifying the run of: {:?}", manifest);
+            let mismatches = exit_on_error(verify::verify_run(&ProverClient::new(), manifest));
+            for mismatch in &mismatches {
+                eprintln!("Mismatch: {}", mismatch);
+            }
+            if !mismatches.is_empty() {
+                exit_on_error(Err(format!("Found {} mismatches", mismatches.len())))
+            }
+            println!("All proofs are valid!");
+        }
+        Command::Inspect { manifest, number } => {
+            exit_on_error(inspect(manifest, *number));
+        }
     }
-    let exec_type = if args.execute {
-        ExecutionType::Execute
-    } else {
-        ExecutionType::Prove
-    };
-
-    ///////// Setup the prover client.
-    let client = ProverClient::new();
+}
 
-    ///////// Setup the inputs.
+/// Reads the index & the query of the given data file.
+fn read_inputs(path: &Path) -> (Vec<EmbeddedData<Data>>, Vec<f32>) {
     // Read samples from file
     let samples_bytes =
-        std::fs::read(args.path.with_extension("index.json")).expect("failed to read the file");
+        std::fs::read(path.with_extension("index.json")).expect("failed to read the file");
     let samples_data: Vec<EmbeddedData<Data>> =
         serde_json::from_slice(&samples_bytes).expect("failed to parse JSON");
-    let samples = samples_data
-        .into_iter()
-        .map(|data| data.embeddings)
-        .collect::<Vec<Vec<f32>>>();
 
     // Read query from file
     let query_bytes =
-        std::fs::read(args.path.with_extension("query.json")).expect("failed to read the file");
+        std::fs::read(path.with_extension("query.json")).expect("failed to read the file");
     let query: Vec<f32> = serde_json::from_slice(&query_bytes).expect("failed to parse JSON");
+    (samples_data, query)
+}
 
-    match exec_type {
-        ExecutionType::Execute => {
-            // pass everything at once for execution
-            let mut stdin = SP1Stdin::new();
-            stdin.write(&samples);
-            stdin.write(&query);
-
-            // Execute the program
-            println!("Executing program.");
-            let (output, report) = client.execute(PROGRAM_ELF, stdin).run().unwrap();
-            println!("Program executed successfully.");
-
-            // Read the output.
-            let idx = u32::from_ne_bytes(
-                output.as_slice()[0..4]
-                    .try_into()
-                    .expect("failed to read u32 from output"),
-            );
+/// Checks that there is something to search, i.e. that the dataset is not empty and that a sample
+/// passes the filter if there is one, since a search over no samples has no final proof.
+fn check_selection(
+    samples_data: &[EmbeddedData<Data>],
+    params: &SearchParams,
+) -> Result<(), String> {
+    if samples_data.is_empty() {
+        return Err("The dataset is empty".to_string());
+    }
+    if let Some(filter) = &params.filter {
+        if !samples_data
+            .iter()
+            .any(|data| filter.matches(&data.metadata))
+        {
+            return Err(format!("No sample passes the filter {}", filter));
+        }
+    }
+    Ok(())
+}
 
-            // Read the commitments
-            let query_commitment = &output.as_slice()[4..36];
-            println!("Query Commitment: {}", hex::encode(query_commitment));
-            let samples_commitment = &output.as_slice()[36..68];
-            println!("Samples Commitment: {}", hex::encode(samples_commitment));
-            let output_commitment = &output.as_slice()[68..100];
-            println!("Output Commitment: {}", hex::encode(output_commitment));
-
-            let expected_idx = vnns_lib::compute_best_sample(&samples, &query);
-            assert_eq!(idx, expected_idx as u32);
-            println!("Values are correct!");
-
-            // Record the number of cycles executed.
-            println!("Number of cycles: {}", report.total_instruction_count());
-        }
-        ExecutionType::Prove => {
-            // setup the program for proving.
-            let (pk, vk) = client.setup(PROGRAM_ELF);
-            let (agg_pk, agg_vk) = client.setup(AGGREGATOR_ELF);
-
-            // generate similarity proofs
-            println!("Proving all chunks (batch size {})", args.batch_size);
-            let mut proofs = Vec::new();
-            let mut current_samples = samples;
-            while current_samples.len() > args.batch_size {
-                // we will collect the best samples for this iteration here
-                let mut best_samples = Vec::new();
-
-                // process each chunk within the current samples
-                for (chunk_idx, chunk) in current_samples.chunks(args.batch_size).enumerate() {
-                    println!("Generating proof for chunk {}.", chunk_idx);
-                    let mut stdin = SP1Stdin::new();
-                    stdin.write(&chunk);
-                    stdin.write(&query);
-
-                    // create proof
-                    let proof = client
-                        .prove(&pk, stdin)
-                        .compressed()
-                        .run()
-                        .expect("failed to generate proof");
-
-                    // find idx from the public output and choose the best sample
-                    let idx = u32::from_ne_bytes(
-                        proof.public_values.as_slice()[0..4]
-                            .try_into()
-                            .expect("failed to read u32 from output"),
-                    );
-                    println!("Closest index: {}", idx);
-                    let query_commitment = &proof.public_values.as_slice()[4..36];
-                    println!("Query Commitment: {}", hex::encode(query_commitment));
-                    let samples_commitment = &proof.public_values.as_slice()[36..68];
-                    println!("Samples Commitment: {}", hex::encode(samples_commitment));
-                    let output_commitment = &proof.public_values.as_slice()[68..100];
-                    println!("Output Commitment: {}", hex::encode(output_commitment));
-
-                    best_samples.push(idx);
-
-                    // store proof for aggregation
-                    proofs.push(proof);
+/// Executes the program over the whole dataset at once, and checks its results against the host.
+fn execute(client: &ProverClient, search: &SearchArgs) {
+    let (samples_data, query) = read_inputs(&search.path);
+    exit_on_error(check_selection(&samples_data, &search.params()));
+    let samples = embedding_matrix(&samples_data);
+    let data = SearchData::load(&search.path, search.quantized, &samples_data, &query, None);
+    println!("Dataset root: {}", hex::encode(data.tree.root()));
+    println!("Dataset size: {}", data.len());
+    let all_idxs = (0..data.len()).collect::<Vec<_>>();
+    let params = search.params();
+    let mode = params.mode;
+
+    // pass everything at once for execution, there is no previous round to be verified
+    let mut stdin = SP1Stdin::new();
+    data.write_input(&mut stdin, &all_idxs, &params, [0; 8], &[]);
+
+    // Execute the program
+    println!("Executing program.");
+    let (output, report) = client.execute(PROGRAM_ELF, stdin).run().unwrap();
+    println!("Program executed successfully.");
+
+    // Read the output.
+    let output = PublicOutputs::decode(output.as_slice()).expect("failed to decode public values");
+    print_outputs(&output);
+
+    match data.search(&all_idxs, &params) {
+        Ok(expected) => {
+            assert_eq!(output.indices(), expected.idxs);
+            assert_eq!(output.scores, expected.scores);
+            assert_eq!(output.runner_up, expected.runner_up);
+        }
+        Err(err) => assert_eq!(output.status, err.code()),
+    }
+    println!("Values are correct!");
+    for (rank, idx) in output.indices().into_iter().enumerate() {
+        println!(
+            "#{} {} ({})",
+            rank + 1,
+            samples_data[idx].data,
+            samples_data[idx].hash
+        );
+    }
+
+    // compare quantized results with the exact ones
+    if matches!(data.embeddings, Embeddings::Quantized { .. }) && !output.is_rejected() {
+        let exact_idxs = Embeddings::Float {
+            samples: samples.clone(),
+            query: query.clone(),
+        }
+        .search(&all_idxs, &data.metadata, &params)
+        .expect("failed to compute exact results")
+        .idxs;
+        let recall = vnns_lib::recall(&exact_idxs, &output.indices());
+        println!("Exact indices: {:?}", exact_idxs);
+        println!("Recall ({}): {}", mode, recall);
+    }
+
+    // Record the number of cycles executed.
+    println!("Number of cycles: {}", report.total_instruction_count());
+}
+
+/// Proves every chunk of the search, saving each proof next to the data file along with the
+/// manifest of the run, and aggregates them if requested.
+fn prove(client: &ProverClient, search: &SearchArgs, options: &ProveArgs) {
+    let (samples_data, query) = read_inputs(&search.path);
+    exit_on_error(check_selection(&samples_data, &search.params()));
+    // a resumed run keeps the salt of the saved opening, so that its query commitment holds
+    let salt = options.salted.then(|| {
+        options
+            .resume
+            .then(|| QueryOpening::load_salt(&search.path.with_extension("query.opening.json")))
+            .flatten()
+            .unwrap_or_else(rand::random::<Hash>)
+    });
+    let data = SearchData::load(&search.path, search.quantized, &samples_data, &query, salt);
+    println!("Dataset root: {}", hex::encode(data.tree.root()));
+    println!("Dataset size: {}", data.len());
+
+    // the opening of a salted query commitment is kept next to the proofs, and must stay private
+    if let Some(salt) = data.salt {
+        let opening = QueryOpening {
+            commitment: hex::encode(data.query_commitment()),
+            salt: hex::encode(salt),
+            query: query.clone(),
+            quantized_query: match &data.embeddings {
+                Embeddings::Float { .. } => None,
+                Embeddings::Quantized { query, .. } => Some(query.clone()),
+            },
+        };
+        let opening_path = search.path.with_extension("query.opening.json");
+        println!("Saving query opening to: {:?}", opening_path);
+        std::fs::write(
+            opening_path,
+            serde_json::to_vec_pretty(&opening).expect("failed to serialize"),
+        )
+        .expect("failed to save query opening");
+    }
+    let params = search.params();
+    let mode = params.mode;
+
+    // setup the program for proving.
+    let (pk, vk) = client.setup(PROGRAM_ELF);
+
+    // generate similarity proofs
+    println!(
+        "Proving all chunks (batch size {}, metric {}, {})",
+        search.batch_size, search.metric, mode
+    );
+    if let Some(filter) = &params.filter {
+        println!("Filter: {}", filter);
+    }
+    let prove_chunk = |chunk: &[usize], previous: &[SP1ProofWithPublicValues]| {
+        let mut stdin = SP1Stdin::new();
+        let public_values = previous
+            .iter()
+            .map(|proof| proof.public_values.to_vec())
+            .collect::<Vec<_>>();
+        data.write_input(&mut stdin, chunk, &params, vk.hash_u32(), &public_values);
+
+        // the proofs of the previous round are verified within the program
+        for proof in previous {
+            let SP1Proof::Compressed(proof) = proof.proof.clone() else {
+                panic!("expected compressed proof");
+            };
+            stdin.write_proof(proof, vk.vk.clone());
+        }
+
+        // create proof
+        let proof = client
+            .prove(&pk, stdin)
+            .compressed()
+            .run()
+            .expect("failed to generate proof");
+        let output = PublicOutputs::decode(proof.public_values.as_slice())
+            .expect("failed to decode public values");
+        (proof, output)
+    };
+
+    // the state of the run is saved next to the proofs, and must match on resume
+    let run_path = search.path.with_extension("run.json");
+    let header = data.search_outputs(&params, vk.hash_u32());
+    let mut state = RunState {
+        program_vkey: encode_vkey(vk.hash_u32()),
+        dataset_root: hex::encode(header.dataset_root),
+        query_commitment: hex::encode(header.query_commitment),
+        metric: params.metric.to_string(),
+        mode,
+        batch_size: search.batch_size,
+        filter: params.filter.as_ref().map(Filter::to_string),
+        saved: BTreeSet::new(),
+    };
+    if options.resume {
+        match std::fs::read(&run_path) {
+            Ok(bytes) => {
+                let saved: RunState =
+                    serde_json::from_slice(&bytes).expect("failed to parse run state");
+                if !saved.is_same_run(&state) {
+                    eprintln!("Error: The saved run belongs to another search");
+                    std::process::exit(1);
                 }
+                println!("Resuming run with {} saved proofs.", saved.saved.len());
+                state = saved;
+            }
+            Err(_) => println!("No saved run found, starting over."),
+        }
+    }
+    state.save(&run_path);
+    let state = Mutex::new(state);
 
-                // update samples with the results of each chunk
-                current_samples = best_samples
-                    .iter()
-                    .map(|&idx| current_samples[idx as usize].clone())
-                    .collect::<Vec<_>>();
+    // each proof is saved as soon as it exists, under its number within the run, and a
+    // saved proof is reused on resume if it verifies & belongs to the same chunk
+    let reused = AtomicUsize::new(0);
+    let prove_or_resume =
+        |number: usize, chunk: &[usize], previous: &[SP1ProofWithPublicValues]| {
+            let is_saved = state.lock().unwrap().saved.contains(&number);
+            if let Some(proof) = is_saved.then(|| load_proof(&search.path, number)).flatten() {
+                let output = PublicOutputs::decode(proof.public_values.as_slice()).ok();
+                if let Some(output) = output.filter(|output| {
+                    is_reusable(output, &header, chunk, covered_range(chunk, previous))
+                        && client.verify(&proof, &vk).is_ok()
+                }) {
+                    reused.fetch_add(1, Ordering::SeqCst);
+                    return (proof, output);
+                }
             }
 
-            // all sub-chunks are processed, do one more final proof
-            {
+            let (proof, output) = prove_chunk(chunk, previous);
+            save_proof(&search.path, number, &proof);
+            let mut state = state.lock().unwrap();
+            state.saved.insert(number);
+            state.save(&run_path);
+            (proof, output)
+        };
+
+    // round & chunk of each proof, in the same order as the proofs
+    let mut proofs = Vec::new();
+    let mut positions = Vec::new();
+    let mut rejected = false;
+    // the matches of a range search are merged in rounds like the winners of a top-k search
+    let winners = {
+        let mut tournament = Tournament::new(data.len(), search.batch_size);
+        let mut previous_proofs = Vec::new();
+        while !tournament.is_finished() && !rejected {
+            // we will collect the winners of each chunk for this round here
+            let mut winners = Vec::new();
+            let mut round_proofs = Vec::new();
+            let is_final_round = tournament.is_final_round();
+
+            // prove the chunks of the current round concurrently, each along with
+            // the proofs of the previous chunks whose winners it consists of
+            let chunks = tournament.chunks();
+            let previous_chunks = tournament.previous_chunks();
+            let round = tournament.round();
+            let offset = proofs.len() + previous_proofs.len();
+            if is_final_round {
                 println!("Generating proof for final samples.");
-                let mut stdin = SP1Stdin::new();
-                stdin.write(&current_samples);
-                stdin.write(&query);
-                let proof = client
-                    .prove(&pk, stdin)
-                    .compressed()
-                    .run()
-                    .expect("failed to generate proof");
-
-                let query_commitment = &proof.public_values.as_slice()[4..36];
-                println!("Query Commitment: {}", hex::encode(query_commitment));
-                let samples_commitment = &proof.public_values.as_slice()[36..68];
-                println!("Samples Commitment: {}", hex::encode(samples_commitment));
-                let output_commitment = &proof.public_values.as_slice()[68..100];
-                println!("Output Commitment: {}", hex::encode(output_commitment));
-
-                // verify the proof to be sure
-                client.verify(&proof, &vk).expect("failed to verify proof");
-
-                proofs.push(proof);
+            } else {
+                println!(
+                    "Generating proofs for round {} ({} chunks, {} jobs).",
+                    round,
+                    chunks.len(),
+                    options.jobs
+                );
             }
+            let results = prove_chunks(
+                options.jobs,
+                chunks.len(),
+                |chunk_idx| {
+                    let previous = previous_chunks[chunk_idx].clone();
+                    prove_or_resume(
+                        offset + chunk_idx,
+                        &chunks[chunk_idx],
+                        &previous_proofs[previous],
+                    )
+                },
+                |chunk_idx, output| {
+                    println!(
+                        "Proof {} for round {} chunk {}:",
+                        offset + chunk_idx,
+                        round,
+                        chunk_idx
+                    );
+                    print_outputs(output);
+                },
+            );
 
-            // save all proofs & publics to file
-            for (i, proof) in proofs.iter().enumerate() {
-                println!("Saving proof.");
-                let proof_data = bincode::serialize(proof).expect("failed to serialize proof");
-                std::fs::write(args.path.with_extension(format!("{}.proof", i)), proof_data)
-                    .expect("failed to save SP1 proof");
-
-                println!("Saving public input.");
-                std::fs::write(
-                    args.path.with_extension(format!("{}.pub", i)),
-                    proof.public_values.clone(),
-                )
-                .expect("failed to save SP1 public input");
+            for (chunk_idx, ((proof, output), chunk)) in
+                results.into_iter().zip(&chunks).enumerate()
+            {
+                // find indices from the public output and choose the best samples
+                winners.push(local_indices(chunk, &output.indices()));
+                rejected = output.is_rejected();
+
+                // verify the final proof, or the one that rejects the input, to be sure
+                if is_final_round || rejected {
+                    client.verify(&proof, &vk).expect("failed to verify proof");
+                }
+
+                // the final proof accounts for the whole dataset
+                if is_final_round && !rejected {
+                    assert_eq!(output.dataset_size, data.len());
+                    assert!(output.covers_dataset());
+                    assert_eq!(output.program_vkey, vk.hash_u32());
+                }
+
+                // store proof for the next round & for aggregation, in chunk order
+                round_proofs.push(proof);
+                positions.push((round, chunk_idx));
             }
 
-            // if enabled, aggregate into one final proof
-            if args.aggregate {
-                println!("Aggregating all {} proofs.", proofs.len());
-                let mut stdin = SP1Stdin::new();
-                let agg_inputs: Vec<AggregationInput> = proofs
-                    .into_iter()
-                    .map(|proof| AggregationInput {
-                        proof,
-                        vk: vk.clone(),
-                    })
-                    .collect();
-
-                // write the verification keys to aggregator
-                let vkeys_bytes = agg_inputs
-                    .iter()
-                    .map(|input| input.vk.hash_u32())
-                    .collect::<Vec<_>>();
-                stdin.write::<Vec<[u32; 8]>>(&vkeys_bytes);
+            // move on with the results of each chunk, while every match of a range search wins,
+            // so that its matches must still fit into fewer chunks
+            if !rejected {
+                let num_winners = winners.iter().map(Vec::len).collect::<Vec<_>>();
+                if !tournament.makes_progress(&num_winners) {
+                    exit_on_error::<()>(Err(format!(
+                        "Too many matches to merge within batch size {}",
+                        search.batch_size
+                    )));
+                }
+                tournament.advance(&winners);
+            }
+            proofs.extend(previous_proofs);
+            previous_proofs = round_proofs;
+        }
+        proofs.extend(previous_proofs);
+
+        if rejected {
+            Vec::new()
+        } else {
+            tournament.into_winners()
+        }
+    };
+
+    // map the winners back to the dataset
+    if rejected {
+        println!("Input has been rejected, stopped proving.");
+    }
+    for (rank, Contestant { idx, path }) in winners.iter().enumerate() {
+        println!(
+            "#{} {} ({})",
+            rank + 1,
+            samples_data[*idx].data,
+            samples_data[*idx].hash
+        );
+        println!("Dataset index: {}", idx);
+        for step in path {
+            println!(
+                "  round {} chunk {} index {}",
+                step.round, step.chunk, step.local_idx
+            );
+        }
+    }
+
+    // every proof has already been saved along with its public values
+    println!(
+        "Saved {} proofs, {} of which were resumed.",
+        proofs.len(),
+        reused.load(Ordering::SeqCst)
+    );
+
+    // describe every proof within the manifest, which is saved again once aggregated
+    let manifest_path = Manifest::path(&search.path);
+    let mut manifest = Manifest {
+        dataset: file_name(&search.path),
+        dataset_size: data.len(),
+        dataset_root: hex::encode(header.dataset_root),
+        model: options.model.clone(),
+        metric: params.metric.to_string(),
+        mode,
+        batch_size: search.batch_size,
+        quantized: search.quantized,
+        filter: params.filter.as_ref().map(Filter::to_string),
+        query_commitment: hex::encode(header.query_commitment),
+        program_vkey: encode_vkey(vk.hash_u32()),
+        proofs: proofs
+            .iter()
+            .zip(&positions)
+            .enumerate()
+            .map(|(number, (proof, position))| {
+                ProofEntry::new(
+                    &search.path,
+                    number,
+                    *position,
+                    vk.hash_u32(),
+                    proof.public_values.as_slice(),
+                )
+            })
+            .collect(),
+        receipt_path: None,
+        aggregation: None,
+    };
+
+    // within a private dataset, the results are opened by a receipt instead of the index
+    if options.private && !rejected {
+        let final_idx = exit_on_error(
+            proofs
+                .len()
+                .checked_sub(1)
+                .ok_or_else(|| "There is no final proof to open".to_string()),
+        );
+        let final_outputs = PublicOutputs::decode(proofs[final_idx].public_values.as_slice())
+            .expect("failed to decode public values");
+        let receipts = winners
+            .iter()
+            .map(|Contestant { idx, .. }| data.receipt(*idx, &samples_data[*idx]))
+            .collect::<Vec<_>>();
+        assert!(receipts
+            .iter()
+            .all(|receipt| receipt.verify(&final_outputs)));
+
+        println!("Saving result receipt.");
+        manifest.receipt_path = Some(file_name(&search.path.with_extension("receipt.json")));
+        std::fs::write(
+            search.path.with_extension("receipt.json"),
+            serde_json::to_vec_pretty(&receipts).expect("failed to serialize"),
+        )
+        .expect("failed to save result receipt");
+        println!(
+            "Share proof {} along with the receipt, the others commit to more items.",
+            final_idx
+        );
+    }
+
+    println!("Saving manifest to: {:?}", manifest_path);
+    manifest.save(&manifest_path);
 
-                // write the public values to aggregator
-                let public_values_bytes = agg_inputs
+    // if enabled, aggregate into one final proof, which only exists for a successful search
+    if options.aggregate && rejected {
+        println!("Input has been rejected, skipped aggregation.");
+    } else if options.aggregate {
+        exit_on_error(aggregate(client, &manifest_path, options.fan_out));
+    }
+}
+
+/// Aggregates the saved proofs of the run with the given manifest into one final proof, saved next
+/// to them along with the inclusion proof of each, and lists it within the manifest.
+///
+/// With a fan-out, the proofs are aggregated by a tree of aggregators instead, each of which
+/// verifies up to that many proofs or aggregators below it, and only the root is saved.
+fn aggregate(
+    client: &ProverClient,
+    manifest_path: &Path,
+    fan_out: Option<usize>,
+) -> Result<(), String> {
+    let mut manifest = Manifest::load(manifest_path)?;
+    let path = Manifest::resolve(manifest_path, &manifest.dataset);
+    let (_, vk) = client.setup(PROGRAM_ELF);
+    let (agg_pk, agg_vk) = client.setup(AGGREGATOR_ELF);
+    if manifest.program_vkey != encode_vkey(vk.hash_u32()) {
+        return Err("The proofs belong to another version of the program".to_string());
+    }
+
+    // the public values must form a successful search before anything is proven
+    let proofs = manifest
+        .proofs
+        .iter()
+        .map(|entry| Manifest::load_proof(manifest_path, &entry.proof_path))
+        .collect::<Result<Vec<_>, _>>()?;
+    let public_values_bytes = proofs
+        .iter()
+        .map(|proof| proof.public_values.to_vec())
+        .collect::<Vec<_>>();
+    let expected =
+        vnns_lib::aggregate_outputs(vk.hash_u32(), agg_vk.hash_u32(), &public_values_bytes)
+            .map_err(|err| format!("The proofs cannot be aggregated: {}", err))?;
+    let proofs_tree = vnns_lib::proofs_tree(vk.hash_u32(), &public_values_bytes);
+
+    // proves an aggregator over the VNNS proofs starting with the given number, or over the nodes
+    // of the tree below it, which is the root of the tree if it has no height
+    let final_public_values = public_values_bytes[public_values_bytes.len() - 1].clone();
+    let prove_node =
+        |start: Option<usize>, height: Option<u32>, children: &[SP1ProofWithPublicValues]| {
+            let mut stdin = SP1Stdin::new();
+
+            // write the verification keys of the VNNS program & of the aggregator itself
+            stdin.write::<[u32; 8]>(&vk.hash_u32());
+            stdin.write::<[u32; 8]>(&agg_vk.hash_u32());
+            stdin.write(&height);
+            stdin.write(&start);
+
+            // write the public values to aggregator, and the final ones at the root of a tree
+            stdin.write::<Vec<Vec<u8>>>(
+                &children
                     .iter()
-                    .map(|input| input.proof.public_values.to_vec())
-                    .collect::<Vec<_>>();
-                stdin.write::<Vec<Vec<u8>>>(&public_values_bytes);
-
-                // write the proofs
-                //
-                // Note: this data will not actually be read by the aggregation program, instead it will be
-                // witnessed by the prover during the recursive aggregation process inside SP1 itself.
-                for input in agg_inputs {
-                    let SP1Proof::Compressed(proof) = input.proof.proof else {
-                        panic!("expected compressed proof");
-                    };
-                    stdin.write_proof(proof, input.vk.vk);
-                }
+                    .map(|proof| proof.public_values.to_vec())
+                    .collect(),
+            );
+            if start.is_none() && height.is_none() {
+                stdin.write::<Vec<u8>>(&final_public_values);
+            }
 
-                println!("Proving the aggregated proof.");
-                let proof = client
-                    .prove(&agg_pk, stdin)
-                    .compressed()
-                    .run()
-                    .expect("failed to generate aggregation proof");
-                println!("Successfully generated aggregation proof!");
-
-                // verify the proof
-                client
-                    .verify(&proof, &agg_vk)
-                    .expect("failed to verify proof");
-                println!("Successfully verified aggregation proof!");
-
-                // create & save proof
-                println!("Saving proof.");
-                let proof_data = bincode::serialize(&proof).expect("failed to serialize proof");
-                std::fs::write(args.path.with_extension("agg.proof"), proof_data)
-                    .expect("failed to save SP1 Proof file");
-
-                // save public input
-                println!("Saving public inputs.");
-                std::fs::write(args.path.with_extension("agg.pub"), proof.public_values)
-                    .expect("failed to save SP1 public input");
+            // write the proofs
+            //
+            // Note: this data will not actually be read by the aggregation program, instead it
+            // will be witnessed by the prover during the recursive aggregation process inside SP1
+            // itself.
+            let children_vk = if start.is_some() { &vk } else { &agg_vk };
+            for proof in children {
+                let SP1Proof::Compressed(proof) = proof.proof.clone() else {
+                    panic!("expected compressed proof");
+                };
+                stdin.write_proof(proof, children_vk.vk.clone());
             }
+
+            client
+                .prove(&agg_pk, stdin)
+                .compressed()
+                .run()
+                .expect("failed to generate aggregation proof")
+        };
+
+    // aggregate at once, or by a tree where each aggregator verifies up to fan-out proofs or nodes
+    let fan_out = fan_out.unwrap_or(proofs.len());
+    let proof = if proofs.len() <= fan_out {
+        println!("Aggregating all {} proofs.", proofs.len());
+        prove_node(Some(0), None, &proofs)
+    } else {
+        println!(
+            "Aggregating all {} proofs by a tree with fan-out {}.",
+            proofs.len(),
+            fan_out
+        );
+        let mut height = fan_out.trailing_zeros();
+        let mut nodes = proofs
+            .chunks(fan_out)
+            .enumerate()
+            .map(|(i, children)| {
+                let start = i * fan_out;
+                println!("Aggregating proofs {}..{}.", start, start + children.len());
+                prove_node(Some(start), Some(height), children)
+            })
+            .collect::<Vec<_>>();
+        while nodes.len() > fan_out {
+            height += fan_out.trailing_zeros();
+            println!("Aggregating {} nodes at height {}.", nodes.len(), height);
+            nodes = nodes
+                .chunks(fan_out)
+                .map(|children| prove_node(None, Some(height), children))
+                .collect();
         }
+        println!("Proving the root of {} nodes.", nodes.len());
+        prove_node(None, None, &nodes)
+    };
+    println!("Successfully generated aggregation proof!");
+
+    // verify the proof
+    client
+        .verify(&proof, &agg_vk)
+        .expect("failed to verify proof");
+    println!("Successfully verified aggregation proof!");
+    let aggregated = AggregatedOutputs::decode(proof.public_values.as_slice())
+        .expect("failed to decode aggregated public values");
+    print_aggregated(&aggregated);
+    assert_eq!(aggregated, expected);
+
+    // create & save proof
+    println!("Saving proof.");
+    let proof_data = bincode::serialize(&proof).expect("failed to serialize proof");
+    std::fs::write(path.with_extension("agg.proof"), proof_data)
+        .expect("failed to save SP1 Proof file");
+
+    // save public input
+    println!("Saving public inputs.");
+    std::fs::write(path.with_extension("agg.pub"), proof.public_values)
+        .expect("failed to save SP1 public input");
+
+    // save the inclusion proof of each proof within the aggregation
+    println!("Saving inclusion proofs.");
+    let mut inclusion_paths = Vec::new();
+    for i in 0..proofs_tree.num_leaves() {
+        let inclusion = Inclusion {
+            root: hex::encode(proofs_tree.root()),
+            hash: hex::encode(proofs_tree.leaf(i)),
+            proof: proofs_tree.proof(i),
+        };
+        let inclusion_path = path.with_extension(format!("{}.agg.inclusion.json", i));
+        std::fs::write(
+            &inclusion_path,
+            serde_json::to_vec_pretty(&inclusion).expect("failed to serialize"),
+        )
+        .expect("failed to save inclusion proof");
+        inclusion_paths.push(file_name(&inclusion_path));
+    }
+
+    manifest.aggregation = Some(AggregationEntry {
+        vkey: encode_vkey(agg_vk.hash_u32()),
+        public_values: hex::encode(aggregated.encode()),
+        proof_path: file_name(&path.with_extension("agg.proof")),
+        public_values_path: file_name(&path.with_extension("agg.pub")),
+        inclusion_paths,
+        fan_out: Some(fan_out).filter(|&fan_out| fan_out < manifest.proofs.len()),
+    });
+    println!("Updating manifest: {:?}", manifest_path);
+    manifest.save(manifest_path);
+    Ok(())
+}
+
+/// Prints the public values of the proofs listed by the manifest, or of the one with the given
+/// number, along with the aggregated proof if any.
+fn inspect(manifest_path: &Path, number: Option<usize>) -> Result<(), String> {
+    let manifest = Manifest::load(manifest_path)?;
+    println!(
+        "Dataset: {:?} ({} items)",
+        manifest.dataset, manifest.dataset_size
+    );
+    println!("Model: {}", manifest.model);
+    println!("Batch Size: {}", manifest.batch_size);
+    println!("Number of Proofs: {}", manifest.proofs.len());
+
+    let entries = match number {
+        Some(number) => vec![manifest
+            .proofs
+            .iter()
+            .find(|entry| entry.number == number)
+            .ok_or_else(|| format!("There is no proof {} within the run", number))?],
+        None => manifest.proofs.iter().collect(),
+    };
+    for entry in entries {
+        println!(
+            "Proof {} for round {} chunk {}:",
+            entry.number, entry.round, entry.chunk
+        );
+        let outputs = hex::decode(&entry.public_values)
+            .map_err(|err| err.to_string())
+            .and_then(|bytes| PublicOutputs::decode(&bytes).map_err(|err| err.to_string()))
+            .map_err(|err| format!("Malformed public values of proof {}: {}", entry.number, err))?;
+        print_outputs(&outputs);
+    }
+
+    if let Some(receipt_path) = manifest.receipt_path.as_ref().filter(|_| number.is_none()) {
+        println!("Result receipt: {:?}", receipt_path);
+    }
+    if let Some(aggregation) = manifest.aggregation.as_ref().filter(|_| number.is_none()) {
+        println!("Aggregated proof:");
+        let aggregated = hex::decode(&aggregation.public_values)
+            .map_err(|err| err.to_string())
+            .and_then(|bytes| AggregatedOutputs::decode(&bytes).map_err(|err| err.to_string()))
+            .map_err(|err| format!("Malformed aggregated public values: {}", err))?;
+        print_aggregated(&aggregated);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+/// Public values of a successful chunk of a top-k search, with the given covered range &
+/// results whose hashes & scores derive from their indices, as a fixture of the tests.
+pub(crate) fn test_chunk(covered: Range<usize>, results: &[usize]) -> PublicOutputs {
+    PublicOutputs {
+        status: STATUS_OK,
+        query_commitment: [1; 32],
+        dataset_root: [2; 32],
+        dataset_size: 8,
+        program_vkey: [3; 8],
+        covered,
+        metric_id: 0,
+        mode: SearchMode::TopK { k: 1 },
+        quantization: None,
+        filter: None,
+        results: results.iter().map(|&idx| (idx, [idx as u8; 32])).collect(),
+        scores: results.iter().map(|&idx| idx as Score).collect(),
+        runner_up: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_prove_chunks() {
+        // later chunks finish first, yet the results & the reports are in chunk order
+        for jobs in [1, 3, 8] {
+            let mut reported = Vec::new();
+            let results = prove_chunks(
+                jobs,
+                6,
+                |idx| {
+                    std::thread::sleep(Duration::from_millis(10 * (6 - idx) as u64));
+                    (idx, test_chunk(idx..idx + 1, &[idx]))
+                },
+                |idx, output| reported.push((idx, output.indices())),
+            );
+            let proofs = results.iter().map(|(proof, _)| *proof).collect::<Vec<_>>();
+            assert_eq!(proofs, (0..6).collect::<Vec<_>>(), "{} jobs", jobs);
+            assert_eq!(
+                reported,
+                (0..6).map(|idx| (idx, vec![idx])).collect::<Vec<_>>()
+            );
+        }
+
+        // the results end with the first rejected chunk, even if later ones are done
+        let results = prove_chunks(
+            3,
+            6,
+            |idx| {
+                let status = match idx {
+                    2 => VnnsError::EmptySamples.code(),
+                    _ => STATUS_OK,
+                };
+                let output = PublicOutputs {
+                    status,
+                    ..test_chunk(idx..idx + 1, &[])
+                };
+                (idx, output)
+            },
+            |_, _| {},
+        );
+        assert_eq!(results.len(), 3);
+        assert!(results[2].1.is_rejected());
+        assert_eq!(
+            prove_chunks(4, 0, |idx| (idx, test_chunk(0..0, &[])), |_, _| {}).len(),
+            0
+        );
+    }
+
+    fn run_state() -> RunState {
+        RunState {
+            program_vkey: encode_vkey([3; 8]),
+            dataset_root: hex::encode([2; 32]),
+            query_commitment: hex::encode([1; 32]),
+            metric: Metric::L2.to_string(),
+            mode: SearchMode::TopK { k: 1 },
+            batch_size: 4,
+            filter: None,
+            saved: BTreeSet::from([0, 1]),
+        }
+    }
+
+    #[test]
+    fn test_is_same_run() {
+        // another progress of the same run, through its saved state
+        let state = run_state();
+        let bytes = serde_json::to_vec_pretty(&state).unwrap();
+        let saved: RunState = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(saved, state);
+        let progressed = RunState {
+            saved: BTreeSet::from([0, 1, 2, 3]),
+            ..state.clone()
+        };
+        assert!(progressed.is_same_run(&state));
+
+        // another program, dataset, query, metric, mode, batch size or filter
+        let others = [
+            RunState {
+                program_vkey: encode_vkey([4; 8]),
+                ..state.clone()
+            },
+            RunState {
+                dataset_root: hex::encode([0; 32]),
+                ..state.clone()
+            },
+            RunState {
+                query_commitment: hex::encode([0; 32]),
+                ..state.clone()
+            },
+            RunState {
+                metric: Metric::Cosine.to_string(),
+                ..state.clone()
+            },
+            RunState {
+                mode: SearchMode::TopK { k: 2 },
+                ..state.clone()
+            },
+            RunState {
+                batch_size: 8,
+                ..state.clone()
+            },
+            RunState {
+                filter: Some("price=..100".to_string()),
+                ..state.clone()
+            },
+        ];
+        for other in others {
+            assert!(!other.is_same_run(&state), "{:?}", other);
+        }
+    }
+
+    #[test]
+    fn test_is_reusable() {
+        let search = test_chunk(0..0, &[]);
+        let saved = test_chunk(4..8, &[6]);
+        assert!(is_reusable(&saved, &search, &[4, 5, 6, 7], Some(4..8)));
+
+        // a chunk of a later round, which covers the chunks of its previous proofs
+        assert!(is_reusable(&saved, &search, &[1, 6], Some(4..8)));
+
+        // another chunk, a result outside of the chunk, or no chunk at all
+        assert!(!is_reusable(&saved, &search, &[0, 1, 2, 3], Some(0..4)));
+        assert!(!is_reusable(&saved, &search, &[4, 5], Some(4..8)));
+        assert!(!is_reusable(&saved, &search, &[], None));
+
+        // a rejected proof, or a proof of another search, is proven again
+        let rejected = PublicOutputs {
+            status: VnnsError::EmptySamples.code(),
+            ..saved.clone()
+        };
+        assert!(!is_reusable(&rejected, &search, &[4, 5, 6, 7], Some(4..8)));
+        let other_query = PublicOutputs {
+            query_commitment: [0; 32],
+            ..search.clone()
+        };
+        assert!(!is_reusable(
+            &saved,
+            &other_query,
+            &[4, 5, 6, 7],
+            Some(4..8)
+        ));
+        let other_size = PublicOutputs {
+            dataset_size: 9,
+            ..search
+        };
+        assert!(!is_reusable(&saved, &other_size, &[4, 5, 6, 7], Some(4..8)));
     }
 }
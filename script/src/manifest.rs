@@ -0,0 +1,268 @@
+//! Manifest of a proving run, which describes every saved proof so that downstream tools can find
+//! them without probing for files.
+
+use serde::{Deserialize, Serialize};
+use sp1_sdk::SP1ProofWithPublicValues;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use vnns_lib::{PublicOutputs, Score, SearchMode};
+
+/// Manifest of a proving run, saved with the extension `.manifest.json` next to the proofs.
+///
+/// All file paths are relative to the directory of the manifest.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Data file whose index & query were searched.
+    pub dataset: PathBuf,
+    /// Number of items within the dataset.
+    pub dataset_size: usize,
+    /// Hex encoded Merkle root of the dataset, in the searched representation.
+    pub dataset_root: String,
+    /// Embedding model of the dataset & the query.
+    pub model: String,
+    pub metric: String,
+    pub mode: SearchMode,
+    pub batch_size: usize,
+    /// Whether the quantized index was searched.
+    pub quantized: bool,
+    pub filter: Option<String>,
+    /// Hex encoded query commitment, salted or not.
+    pub query_commitment: String,
+    /// Hex encoded verifying key digest of the VNNS program.
+    pub program_vkey: String,
+    /// Every proof of the run, in the order they were generated, the last one being the final
+    /// proof unless the input was rejected.
+    pub proofs: Vec<ProofEntry>,
+    /// Result receipt of a private dataset, if any.
+    pub receipt_path: Option<PathBuf>,
+    /// Aggregation of all proofs, if any.
+    pub aggregation: Option<AggregationEntry>,
+}
+
+/// A proof of a single chunk.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProofEntry {
+    /// Number of the proof within the run, which is part of its file names.
+    pub number: usize,
+    /// Round of the tournament, where the matches of a range search are merged in later rounds.
+    pub round: usize,
+    /// Chunk within the round.
+    pub chunk: usize,
+    /// Range of dataset indices that the proof accounts for.
+    pub covered: Range<usize>,
+    /// Hex encoded verifying key digest that the proof is verified against.
+    pub vkey: String,
+    /// Status code, see `VnnsError::code`.
+    pub status: u8,
+    /// Dataset indices of the results, in the committed order.
+    pub results: Vec<usize>,
+    /// Score of each result, see `vnns_lib::Score`.
+    pub scores: Vec<Score>,
+    /// Score of the runner-up, if any.
+    pub runner_up: Option<Score>,
+    /// Margin between the farthest result & the runner-up, if any.
+    pub margin: Option<Score>,
+    /// Hex encoded public values.
+    pub public_values: String,
+    pub proof_path: PathBuf,
+    pub public_values_path: PathBuf,
+}
+
+/// The aggregated proof of a run.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AggregationEntry {
+    /// Hex encoded verifying key digest of the aggregation program.
+    pub vkey: String,
+    /// Hex encoded public values, see `vnns_lib::AggregatedOutputs`.
+    pub public_values: String,
+    pub proof_path: PathBuf,
+    pub public_values_path: PathBuf,
+    /// Inclusion proof of each proof within the aggregation, in the order of the proofs.
+    pub inclusion_paths: Vec<PathBuf>,
+    /// Fan-out of the aggregation tree, or `None` if all proofs were aggregated at once.
+    #[serde(default)]
+    pub fan_out: Option<usize>,
+}
+
+impl ProofEntry {
+    /// Describes the saved proof with the given number & public values, which is verified against
+    /// the given key.
+    pub fn new(
+        path: &Path,
+        number: usize,
+        (round, chunk): (usize, usize),
+        vkey: [u32; 8],
+        public_values: &[u8],
+    ) -> Self {
+        let outputs = PublicOutputs::decode(public_values).expect("failed to decode public values");
+        Self {
+            number,
+            round,
+            chunk,
+            covered: outputs.covered.clone(),
+            vkey: encode_vkey(vkey),
+            status: outputs.status,
+            results: outputs.indices(),
+            margin: outputs.margin(),
+            scores: outputs.scores,
+            runner_up: outputs.runner_up,
+            public_values: hex::encode(public_values),
+            proof_path: file_name(&proof_path(path, number)),
+            public_values_path: file_name(&public_values_path(path, number)),
+        }
+    }
+}
+
+impl Manifest {
+    /// Path of the manifest of the given data file.
+    pub fn path(path: &Path) -> PathBuf {
+        path.with_extension("manifest.json")
+    }
+
+    /// Loads the manifest at the given path.
+    pub fn load(manifest_path: &Path) -> Result<Self, String> {
+        let bytes = std::fs::read(manifest_path)
+            .map_err(|err| format!("failed to read {:?}: {}", manifest_path, err))?;
+        serde_json::from_slice(&bytes)
+            .map_err(|err| format!("failed to parse {:?}: {}", manifest_path, err))
+    }
+
+    pub fn save(&self, manifest_path: &Path) {
+        std::fs::write(
+            manifest_path,
+            serde_json::to_vec_pretty(self).expect("failed to serialize"),
+        )
+        .expect("failed to save manifest");
+    }
+
+    /// Resolves a path listed by the manifest at the given path.
+    pub fn resolve(manifest_path: &Path, path: &Path) -> PathBuf {
+        manifest_path.parent().unwrap_or(Path::new("")).join(path)
+    }
+
+    /// Loads a saved proof listed by the manifest at the given path.
+    pub fn load_proof(
+        manifest_path: &Path,
+        path: &Path,
+    ) -> Result<SP1ProofWithPublicValues, String> {
+        let path = Self::resolve(manifest_path, path);
+        let bytes =
+            std::fs::read(&path).map_err(|err| format!("failed to read {:?}: {}", path, err))?;
+        bincode::deserialize(&bytes).map_err(|err| format!("failed to decode {:?}: {}", path, err))
+    }
+}
+
+/// Path of the proof with the given number within the run of the given data file.
+pub fn proof_path(path: &Path, number: usize) -> PathBuf {
+    path.with_extension(format!("{}.proof", number))
+}
+
+/// Path of the public values of the proof with the given number.
+pub fn public_values_path(path: &Path, number: usize) -> PathBuf {
+    path.with_extension(format!("{}.pub", number))
+}
+
+/// Hex encoding of a verifying key digest, as little-endian words.
+pub fn encode_vkey(vkey: [u32; 8]) -> String {
+    hex::encode(vkey.map(u32::to_le_bytes).concat())
+}
+
+/// File name of a path next to the manifest.
+pub fn file_name(path: &Path) -> PathBuf {
+    PathBuf::from(path.file_name().expect("expected a file path"))
+}
+
+/// Manifest of a top-k run over `data.json` with the given public values, each proof being a chunk
+/// of the first round, as a fixture of the tests.
+#[cfg(test)]
+pub(crate) fn test_manifest(public_values: &[Vec<u8>]) -> Manifest {
+    Manifest {
+        dataset: PathBuf::from("data.json"),
+        dataset_size: 8,
+        dataset_root: hex::encode([2; 32]),
+        model: "nomic-embed-text".to_string(),
+        metric: vnns_lib::Metric::SquaredL2.to_string(),
+        mode: SearchMode::TopK { k: 1 },
+        batch_size: 4,
+        quantized: false,
+        filter: None,
+        query_commitment: hex::encode([1; 32]),
+        program_vkey: encode_vkey([3; 8]),
+        proofs: public_values
+            .iter()
+            .enumerate()
+            .map(|(number, public_values)| {
+                ProofEntry::new(
+                    Path::new("data.json"),
+                    number,
+                    (0, number),
+                    [3; 8],
+                    public_values,
+                )
+            })
+            .collect(),
+        receipt_path: None,
+        aggregation: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_chunk;
+
+    #[test]
+    fn test_manifest_round_trip() {
+        let public_values = vec![
+            test_chunk(0..4, &[1]).encode(),
+            test_chunk(4..8, &[6]).encode(),
+        ];
+        let mut manifest = test_manifest(&public_values);
+        manifest.receipt_path = Some(PathBuf::from("data.receipt.json"));
+        manifest.aggregation = Some(AggregationEntry {
+            vkey: encode_vkey([9; 8]),
+            public_values: "00".to_string(),
+            proof_path: PathBuf::from("data.agg.proof"),
+            public_values_path: PathBuf::from("data.agg.pub"),
+            inclusion_paths: vec![PathBuf::from("data.0.agg.inclusion.json")],
+            fan_out: Some(2),
+        });
+
+        let dir = std::env::temp_dir().join(format!("vnns-manifest-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let manifest_path = Manifest::path(&dir.join("data.json"));
+        manifest.save(&manifest_path);
+        let loaded = Manifest::load(&manifest_path);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(loaded, Ok(manifest.clone()));
+
+        // each entry describes its public values, and its files are next to the manifest
+        let entry = &manifest.proofs[1];
+        assert_eq!(
+            (entry.round, entry.chunk, entry.covered.clone()),
+            (0, 1, 4..8)
+        );
+        assert_eq!(entry.results, vec![6]);
+        assert_eq!(entry.public_values, hex::encode(&public_values[1]));
+        assert_eq!(entry.proof_path, PathBuf::from("data.1.proof"));
+        assert_eq!(entry.public_values_path, PathBuf::from("data.1.pub"));
+        assert_eq!(
+            Manifest::resolve(&manifest_path, &entry.proof_path),
+            dir.join("data.1.proof")
+        );
+        assert_eq!(
+            encode_vkey([1, 0, 0, 0, 0, 0, 0, 2]),
+            format!("01000000{}02000000", "0".repeat(48))
+        );
+
+        // an aggregation of an older manifest has no fan-out
+        let mut json = serde_json::to_value(&manifest).unwrap();
+        json["aggregation"]
+            .as_object_mut()
+            .unwrap()
+            .remove("fan_out");
+        let older: Manifest = serde_json::from_value(json).unwrap();
+        assert_eq!(older.aggregation.unwrap().fan_out, None);
+        assert!(Manifest::load(&dir.join("missing.manifest.json")).is_err());
+    }
+}
@@ -0,0 +1,640 @@
+//! Offline verification of a proving run, from the files listed by its manifest & the local index.
+
+use serde::de::DeserializeOwned;
+use sp1_sdk::{HashableKey, ProverClient, SP1VerifyingKey};
+use std::path::Path;
+use vnns_embedder::{
+    dataset_tree, quantized_tree, Data, EmbeddedData, Inclusion, QuantizedIndex, ResultReceipt,
+};
+use vnns_lib::encoding::query_commitment;
+use vnns_lib::{
+    aggregate_outputs, AggregatedOutputs, Filter, Hash, Metric, PublicOutputs, Quantization,
+    VnnsError, STATUS_OK,
+};
+
+use crate::manifest::{encode_vkey, AggregationEntry, Manifest, ProofEntry};
+use crate::{QueryOpening, AGGREGATOR_ELF, PROGRAM_ELF};
+
+/// Verifies every proof of the run with the given manifest, as well as its aggregation if any,
+/// against the local programs, and checks their public values against the manifest & the local
+/// index & query.
+///
+/// Returns every mismatch that was found, or an error if the run cannot be checked at all.
+pub fn verify_run(client: &ProverClient, manifest_path: &Path) -> Result<Vec<String>, String> {
+    let manifest = Manifest::load(manifest_path)?;
+    let mut mismatches = Vec::new();
+
+    // the proofs must be verified against the local programs
+    let (_, vk) = client.setup(PROGRAM_ELF);
+    let (_, agg_vk) = client.setup(AGGREGATOR_ELF);
+    if manifest.program_vkey != encode_vkey(vk.hash_u32()) {
+        mismatches.push(format!(
+            "Manifest: program vkey {} differs from the local program {}",
+            manifest.program_vkey,
+            encode_vkey(vk.hash_u32())
+        ));
+    }
+
+    // recompute both commitments from the local files
+    let local = local_commitments(manifest_path, &manifest)?;
+    println!("Dataset root: {}", hex::encode(local.dataset_root));
+    println!("Query commitment: {}", hex::encode(local.query_commitment));
+    if manifest.dataset_root != hex::encode(local.dataset_root) {
+        mismatches.push(format!(
+            "Manifest: dataset root {} differs from the local index",
+            manifest.dataset_root
+        ));
+    }
+    if manifest.dataset_size != local.dataset_size {
+        mismatches.push(format!(
+            "Manifest: dataset size {} differs from the local index ({})",
+            manifest.dataset_size, local.dataset_size
+        ));
+    }
+    if manifest.query_commitment != hex::encode(local.query_commitment) {
+        mismatches.push(format!(
+            "Manifest: query commitment {} differs from the local query",
+            manifest.query_commitment
+        ));
+    }
+
+    // each proof on its own
+    let mut public_values = Vec::new();
+    for entry in &manifest.proofs {
+        let count = mismatches.len();
+        let Some(outputs) = verify_proof(client, manifest_path, entry, &vk, &mut mismatches) else {
+            continue;
+        };
+        check_outputs(
+            &manifest,
+            entry,
+            &outputs,
+            &local,
+            vk.hash_u32(),
+            &mut mismatches,
+        );
+        if mismatches.len() == count {
+            println!(
+                "Proof {} (round {}, chunk {}): verified",
+                entry.number, entry.round, entry.chunk
+            );
+        }
+        public_values.push(hex::decode(&entry.public_values).expect("checked by verify_proof"));
+    }
+    if public_values.len() < manifest.proofs.len() {
+        return Ok(mismatches);
+    }
+
+    // the proofs must form a single search over the whole dataset, unless the input was rejected
+    let rejected = manifest
+        .proofs
+        .last()
+        .is_some_and(|entry| entry.status != STATUS_OK);
+    let aggregated = if rejected {
+        println!("Input has been rejected, skipped the search check.");
+        None
+    } else {
+        match aggregate_outputs(vk.hash_u32(), agg_vk.hash_u32(), &public_values) {
+            Ok(aggregated) if aggregated.dataset_size == local.dataset_size => Some(aggregated),
+            Ok(aggregated) => {
+                mismatches.push(format!(
+                    "Search: covers {} samples instead of {}",
+                    aggregated.dataset_size, local.dataset_size
+                ));
+                None
+            }
+            Err(err) => {
+                mismatches.push(format!("Search: {}", err));
+                None
+            }
+        }
+    };
+
+    match (&manifest.aggregation, aggregated) {
+        (Some(aggregation), Some(expected)) => verify_aggregation(
+            client,
+            manifest_path,
+            aggregation,
+            (&vk, &agg_vk),
+            &expected,
+            &public_values,
+            &mut mismatches,
+        ),
+        (Some(_), None) => {
+            mismatches.push("Aggregation: the proofs do not form a complete search".to_string())
+        }
+        (None, _) => println!("No aggregation, skipped."),
+    }
+
+    // the results of a private dataset are opened by the receipt, against the final proof
+    if let (Some(receipt_path), Some(last)) = (&manifest.receipt_path, public_values.last()) {
+        let receipts: Vec<ResultReceipt<Data>> =
+            read_json(&Manifest::resolve(manifest_path, receipt_path))?;
+        let outputs = PublicOutputs::decode(last).expect("checked by verify_proof");
+        for receipt in receipts.iter().filter(|receipt| !receipt.verify(&outputs)) {
+            mismatches.push(format!(
+                "Receipt: item {} is not a result of the final proof",
+                receipt.idx
+            ));
+        }
+    }
+
+    Ok(mismatches)
+}
+
+/// Commitments of a run, recomputed from the local index & query.
+struct LocalCommitments {
+    dataset_root: Hash,
+    dataset_size: usize,
+    /// Salted if the saved opening matches the manifest.
+    query_commitment: Hash,
+    /// Quantization parameters of the local quantized index, if the run is quantized.
+    quantization: Option<Quantization>,
+}
+
+/// Recomputes the commitments of the run from the local index & query.
+///
+/// A private dataset is not shared along with its proofs, so without a local index the dataset root
+/// & size are the ones listed by the manifest, and the quantization is the one committed by the
+/// final proof. The proofs are still checked against them, and the receipt against the final proof.
+fn local_commitments(
+    manifest_path: &Path,
+    manifest: &Manifest,
+) -> Result<LocalCommitments, String> {
+    let path = Manifest::resolve(manifest_path, &manifest.dataset);
+    let query: Vec<f32> = read_json(&path.with_extension("query.json"))?;
+    let salt = QueryOpening::load_salt(&path.with_extension("query.opening.json"));
+
+    let index_path = path.with_extension("index.json");
+    let (dataset_root, dataset_size, quantization) = if index_path.exists() {
+        let data: Vec<EmbeddedData<Data>> = read_json(&index_path)?;
+        if manifest.quantized {
+            let quantized_index: QuantizedIndex = read_json(&path.with_extension("qindex.json"))?;
+            let quantization = quantized_index.quantization;
+            (
+                quantized_tree(&quantization, &quantized_index.embeddings, &data).root(),
+                data.len(),
+                Some(quantization),
+            )
+        } else {
+            (dataset_tree(&data).root(), data.len(), None)
+        }
+    } else {
+        println!("No local index, using the dataset root & size of the manifest.");
+        let dataset_root = hex::decode(&manifest.dataset_root)
+            .ok()
+            .and_then(|bytes| Hash::try_from(bytes).ok())
+            .ok_or_else(|| format!("malformed dataset root {}", manifest.dataset_root))?;
+        let quantization = if manifest.quantized {
+            let committed = manifest
+                .proofs
+                .last()
+                .and_then(|entry| hex::decode(&entry.public_values).ok())
+                .and_then(|public_values| PublicOutputs::decode(&public_values).ok())
+                .and_then(|outputs| outputs.quantization);
+            Some(committed.ok_or("no proof commits the quantization of the dataset")?)
+        } else {
+            None
+        };
+        (dataset_root, manifest.dataset_size, quantization)
+    };
+
+    let commitments = match &quantization {
+        Some(quantization) => {
+            let query = quantization.quantize(&query);
+            [
+                query_commitment(&query, None),
+                query_commitment(&query, salt.as_ref()),
+            ]
+        }
+        None => [
+            query_commitment(&query, None),
+            query_commitment(&query, salt.as_ref()),
+        ],
+    };
+    let [plain, salted] = commitments;
+    let commitment = if manifest.query_commitment == hex::encode(salted) {
+        salted
+    } else {
+        plain
+    };
+    Ok(LocalCommitments {
+        dataset_root,
+        dataset_size,
+        query_commitment: commitment,
+        quantization,
+    })
+}
+
+/// Loads & verifies the proof of the given entry, and checks that its public values are the listed
+/// ones, returning them decoded if so.
+fn verify_proof(
+    client: &ProverClient,
+    manifest_path: &Path,
+    entry: &ProofEntry,
+    vk: &SP1VerifyingKey,
+    mismatches: &mut Vec<String>,
+) -> Option<PublicOutputs> {
+    let label = format!("Proof {}", entry.number);
+    let proof = match Manifest::load_proof(manifest_path, &entry.proof_path) {
+        Ok(proof) => proof,
+        Err(err) => {
+            mismatches.push(format!("{}: {}", label, err));
+            return None;
+        }
+    };
+    if let Err(err) = client.verify(&proof, vk) {
+        mismatches.push(format!("{}: failed to verify: {}", label, err));
+        return None;
+    }
+    if entry.vkey != encode_vkey(vk.hash_u32()) {
+        mismatches.push(format!("{}: listed with vkey {}", label, entry.vkey));
+    }
+
+    check_public_values(
+        &label,
+        manifest_path,
+        proof.public_values.as_slice(),
+        (&entry.public_values, &entry.public_values_path),
+        mismatches,
+    )?;
+    match PublicOutputs::decode(proof.public_values.as_slice()) {
+        Ok(outputs) => Some(outputs),
+        Err(err) => {
+            mismatches.push(format!(
+                "{}: failed to decode public values: {}",
+                label, err
+            ));
+            None
+        }
+    }
+}
+
+/// Checks the decoded public values of a proof against its entry, the manifest & the local
+/// commitments.
+fn check_outputs(
+    manifest: &Manifest,
+    entry: &ProofEntry,
+    outputs: &PublicOutputs,
+    local: &LocalCommitments,
+    vkey: [u32; 8],
+    mismatches: &mut Vec<String>,
+) {
+    let metric = Metric::try_from(outputs.metric_id).map(|metric| metric.to_string());
+    let checks = [
+        (outputs.status == entry.status, "status"),
+        (outputs.covered == entry.covered, "covered range"),
+        (outputs.indices() == entry.results, "results"),
+        (outputs.scores == entry.scores, "scores"),
+        (outputs.runner_up == entry.runner_up, "runner-up"),
+        (outputs.dataset_root == local.dataset_root, "dataset root"),
+        (outputs.dataset_size == local.dataset_size, "dataset size"),
+        (
+            outputs.query_commitment == local.query_commitment,
+            "query commitment",
+        ),
+        (outputs.program_vkey == vkey, "program vkey"),
+        (metric.as_ref() == Ok(&manifest.metric), "metric"),
+        (outputs.mode.to_bytes() == manifest.mode.to_bytes(), "mode"),
+        // the quantization is compared bit for bit, as it is within the item hashes
+        (
+            outputs
+                .quantization
+                .map(|quantization| quantization.to_bytes())
+                == local
+                    .quantization
+                    .map(|quantization| quantization.to_bytes()),
+            "quantization",
+        ),
+        (
+            outputs.filter.as_ref().map(Filter::to_string) == manifest.filter,
+            "filter",
+        ),
+    ];
+    for (_, field) in checks.iter().filter(|(ok, _)| !ok) {
+        mismatches.push(format!("Proof {}: {} mismatch", entry.number, field));
+    }
+}
+
+/// Loads & verifies the aggregated proof, and checks it against the aggregation of the listed
+/// proofs, along with the inclusion proof of each.
+///
+/// The aggregator commits the verifying keys it is given without pinning either, so a valid
+/// aggregated proof says nothing until both committed keys are checked against the local programs.
+fn verify_aggregation(
+    client: &ProverClient,
+    manifest_path: &Path,
+    aggregation: &AggregationEntry,
+    (vk, agg_vk): (&SP1VerifyingKey, &SP1VerifyingKey),
+    expected: &AggregatedOutputs,
+    public_values: &[Vec<u8>],
+    mismatches: &mut Vec<String>,
+) {
+    let label = "Aggregation";
+    let count = mismatches.len();
+    let proof = match Manifest::load_proof(manifest_path, &aggregation.proof_path) {
+        Ok(proof) => proof,
+        Err(err) => return mismatches.push(format!("{}: {}", label, err)),
+    };
+    if let Err(err) = client.verify(&proof, agg_vk) {
+        return mismatches.push(format!("{}: failed to verify: {}", label, err));
+    }
+    if aggregation.vkey != encode_vkey(agg_vk.hash_u32()) {
+        mismatches.push(format!("{}: listed with vkey {}", label, aggregation.vkey));
+    }
+    if check_public_values(
+        label,
+        manifest_path,
+        proof.public_values.as_slice(),
+        (&aggregation.public_values, &aggregation.public_values_path),
+        mismatches,
+    )
+    .is_none()
+    {
+        return;
+    }
+
+    let aggregated = match AggregatedOutputs::decode(proof.public_values.as_slice()) {
+        Ok(aggregated) => aggregated,
+        Err(err) => {
+            return mismatches.push(format!(
+                "{}: failed to decode public values: {}",
+                label, err
+            ))
+        }
+    };
+    if aggregated.program_vkey != vk.hash_u32() {
+        mismatches.push(format!(
+            "{}: commits program vkey {} instead of the local program",
+            label,
+            encode_vkey(aggregated.program_vkey)
+        ));
+    }
+    if aggregated.aggregator_vkey != agg_vk.hash_u32() {
+        mismatches.push(format!(
+            "{}: commits aggregator vkey {} instead of the local aggregator",
+            label,
+            encode_vkey(aggregated.aggregator_vkey)
+        ));
+    }
+    if aggregated.is_rejected() {
+        mismatches.push(format!(
+            "{}: rejected its input: {}",
+            label,
+            VnnsError::describe_code(aggregated.status)
+        ));
+    } else if &aggregated != expected {
+        mismatches.push(format!("{}: differs from the listed proofs", label));
+    }
+
+    // every proof is part of the aggregation at its own index
+    if aggregation.inclusion_paths.len() != public_values.len() {
+        mismatches.push(format!(
+            "{}: {} inclusion proofs for {} proofs",
+            label,
+            aggregation.inclusion_paths.len(),
+            public_values.len()
+        ));
+    }
+    for (number, (inclusion_path, public_values)) in aggregation
+        .inclusion_paths
+        .iter()
+        .zip(public_values)
+        .enumerate()
+    {
+        match read_json::<Inclusion>(&Manifest::resolve(manifest_path, inclusion_path)) {
+            Ok(inclusion)
+                if inclusion.proof.idx == number
+                    && aggregated.is_aggregated(public_values, &inclusion.proof) => {}
+            Ok(_) => mismatches.push(format!(
+                "{}: proof {} is not included by its inclusion proof",
+                label, number
+            )),
+            Err(err) => mismatches.push(format!("{}: {}", label, err)),
+        }
+    }
+    if mismatches.len() == count {
+        println!("Aggregation: verified");
+    }
+}
+
+/// Checks that the public values of a proof are the ones listed by the manifest & saved next to
+/// it, returning `None` if not.
+fn check_public_values(
+    label: &str,
+    manifest_path: &Path,
+    public_values: &[u8],
+    (listed, saved_path): (&str, &Path),
+    mismatches: &mut Vec<String>,
+) -> Option<()> {
+    if hex::decode(listed).ok().as_deref() != Some(public_values) {
+        mismatches.push(format!("{}: public values differ from the manifest", label));
+        return None;
+    }
+    let saved_path = Manifest::resolve(manifest_path, saved_path);
+    match std::fs::read(&saved_path) {
+        Ok(saved) if saved == public_values => {}
+        Ok(_) => mismatches.push(format!(
+            "{}: public values differ from {:?}",
+            label, saved_path
+        )),
+        Err(err) => mismatches.push(format!(
+            "{}: failed to read {:?}: {}",
+            label, saved_path, err
+        )),
+    }
+    Some(())
+}
+
+fn read_json<T: DeserializeOwned>(path: &Path) -> Result<T, String> {
+    let bytes = std::fs::read(path).map_err(|err| format!("failed to read {:?}: {}", path, err))?;
+    serde_json::from_slice(&bytes).map_err(|err| format!("failed to parse {:?}: {}", path, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::test_manifest;
+    use crate::test_chunk;
+    use std::path::PathBuf;
+
+    /// Empty directory of the test with the given name.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vnns-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Local commitments of the run described by [`test_manifest`].
+    fn local() -> LocalCommitments {
+        LocalCommitments {
+            dataset_root: [2; 32],
+            dataset_size: 8,
+            query_commitment: [1; 32],
+            quantization: None,
+        }
+    }
+
+    #[test]
+    fn test_local_commitments_without_index() {
+        // a private dataset, whose proofs are shared along with the query only
+        let dir = test_dir("private");
+        let manifest_path = Manifest::path(&dir.join("data.json"));
+        std::fs::write(dir.join("data.query.json"), "[0.5, -1.0]").unwrap();
+        let query = [0.5f32, -1.0];
+
+        let manifest = test_manifest(&[test_chunk(0..8, &[5]).encode()]);
+        let plain = local_commitments(&manifest_path, &manifest);
+
+        // the quantization is the one committed by the final proof, if any
+        let quantization = Quantization::new(8, 0.5);
+        let quantized_chunk = PublicOutputs {
+            quantization: Some(quantization),
+            ..test_chunk(0..8, &[5])
+        };
+        let quantized = Manifest {
+            quantized: true,
+            ..test_manifest(&[quantized_chunk.encode()])
+        };
+        let quantized_local = local_commitments(&manifest_path, &quantized);
+        let unknown = Manifest {
+            quantized: true,
+            ..manifest.clone()
+        };
+        let unknown_local = local_commitments(&manifest_path, &unknown);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let plain = plain.unwrap();
+        assert_eq!(plain.dataset_root, [2; 32]);
+        assert_eq!(plain.dataset_size, 8);
+        assert_eq!(plain.query_commitment, query_commitment(&query, None));
+        assert_eq!(plain.quantization, None);
+
+        let quantized_local = quantized_local.unwrap();
+        assert_eq!(quantized_local.dataset_size, 8);
+        assert_eq!(
+            quantized_local.query_commitment,
+            query_commitment(&quantization.quantize(&query), None)
+        );
+        assert_eq!(
+            quantized_local
+                .quantization
+                .map(|quantization| quantization.to_bytes()),
+            Some(quantization.to_bytes())
+        );
+        assert!(unknown_local.is_err());
+    }
+
+    #[test]
+    fn test_check_outputs() {
+        let outputs = test_chunk(0..8, &[5]);
+        let manifest = test_manifest(&[outputs.encode()]);
+        let check = |outputs: &PublicOutputs, local: &LocalCommitments, vkey: [u32; 8]| {
+            let mut mismatches = Vec::new();
+            check_outputs(
+                &manifest,
+                &manifest.proofs[0],
+                outputs,
+                local,
+                vkey,
+                &mut mismatches,
+            );
+            mismatches
+        };
+        assert_eq!(check(&outputs, &local(), [3; 8]), Vec::<String>::new());
+
+        // another dataset, another size of it, or another program
+        let other_root = LocalCommitments {
+            dataset_root: [0; 32],
+            ..local()
+        };
+        assert_eq!(
+            check(&outputs, &other_root, [3; 8]),
+            vec!["Proof 0: dataset root mismatch"]
+        );
+        let other_size = LocalCommitments {
+            dataset_size: 9,
+            ..local()
+        };
+        assert_eq!(
+            check(&outputs, &other_size, [3; 8]),
+            vec!["Proof 0: dataset size mismatch"]
+        );
+        assert_eq!(
+            check(&outputs, &local(), [4; 8]),
+            vec!["Proof 0: program vkey mismatch"]
+        );
+
+        // public values that differ from the entry, or from the manifest
+        let other_results = PublicOutputs {
+            results: vec![(4, [4; 32])],
+            ..outputs.clone()
+        };
+        assert_eq!(
+            check(&other_results, &local(), [3; 8]),
+            vec!["Proof 0: results mismatch"]
+        );
+        let other_metric = PublicOutputs {
+            metric_id: Metric::Cosine.into(),
+            ..outputs
+        };
+        assert_eq!(
+            check(&other_metric, &local(), [3; 8]),
+            vec!["Proof 0: metric mismatch"]
+        );
+    }
+
+    #[test]
+    fn test_check_public_values() {
+        let dir = test_dir("public-values");
+        let manifest_path = Manifest::path(&dir.join("data.json"));
+        let saved_path = dir.join("data.0.pub");
+        let public_values = test_chunk(0..8, &[5]).encode();
+        let listed = hex::encode(&public_values);
+        let mut tampered = public_values.clone();
+        tampered[1] = 1;
+
+        let check = |public_values: &[u8], listed: &str| {
+            let mut mismatches = Vec::new();
+            let checked = check_public_values(
+                "Proof 0",
+                &manifest_path,
+                public_values,
+                (listed, Path::new("data.0.pub")),
+                &mut mismatches,
+            );
+            (checked.is_some(), mismatches)
+        };
+        std::fs::write(&saved_path, &public_values).unwrap();
+        let valid = check(&public_values, &listed);
+
+        // the proof commits to other public values than the manifest lists, or the other way round
+        let tampered_proof = check(&tampered, &listed);
+        let tampered_manifest = check(&public_values, &hex::encode(&tampered));
+
+        // the saved `.pub` file has been tampered with, or is missing
+        std::fs::write(&saved_path, &tampered).unwrap();
+        let tampered_file = check(&public_values, &listed);
+        std::fs::remove_file(&saved_path).unwrap();
+        let missing_file = check(&public_values, &listed);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(valid, (true, Vec::new()));
+        let differ = vec!["Proof 0: public values differ from the manifest".to_string()];
+        assert_eq!(tampered_proof, (false, differ.clone()));
+        assert_eq!(tampered_manifest, (false, differ));
+        assert_eq!(
+            tampered_file,
+            (
+                true,
+                vec![format!(
+                    "Proof 0: public values differ from {:?}",
+                    saved_path
+                )]
+            )
+        );
+        assert!(missing_file.0);
+        assert!(missing_file.1[0].starts_with("Proof 0: failed to read"));
+    }
+}
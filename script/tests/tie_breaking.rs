@@ -0,0 +1,103 @@
+//! Differential tests between the host search & the VNNS program executed within the zkVM, on
+//! inputs with ties and NaN distances.
+//!
+//! ```shell
+//! cargo test --release -p vnns-script --test tie_breaking
+//! ```
+
+use sp1_sdk::{ProverClient, SP1Stdin};
+use vnns_lib::encoding::item_hash;
+use vnns_lib::{
+    compute_top_k, EmbeddingMatrix, Hash, MerkleTree, Metadata, Metric, PublicOutputs,
+    Quantization, SearchMode, STATUS_OK,
+};
+
+const PROGRAM_ELF: &[u8] = include_bytes!("../../elf/riscv32im-succinct-vnns-elf");
+
+/// Executes the program & returns the committed status along with the indices.
+fn execute(samples: &EmbeddingMatrix, query: &[f32], k: u32, metric: Metric) -> (u8, Vec<usize>) {
+    let mut stdin = SP1Stdin::new();
+    stdin.write(&u8::from(metric));
+    stdin.write(&SearchMode::TopK { k });
+    stdin.write::<Option<Quantization>>(&None);
+    stdin.write_vec(samples.to_bytes());
+    stdin.write_vec(Vec::new()); // no filter
+    stdin.write_vec(Vec::new()); // no metadata
+    stdin.write(&vec![[0u8; 32]; samples.len()]); // no payloads
+
+    // the samples are the whole dataset
+    let tree = MerkleTree::new(
+        samples
+            .rows()
+            .map(|row| item_hash(row, None, &Metadata::default(), &[0u8; 32]))
+            .collect(),
+    );
+    stdin.write(&tree.root());
+    stdin.write(&samples.len());
+    stdin.write(&[0u32; 8]); // no previous round to be verified
+    stdin.write(&Vec::<Vec<u8>>::new());
+    stdin.write(
+        &(0..samples.len())
+            .map(|idx| tree.proof(idx))
+            .collect::<Vec<_>>(),
+    );
+    stdin.write::<Option<Hash>>(&None); // no salt
+    stdin.write(&query.to_vec());
+
+    let (output, _) = ProverClient::new()
+        .execute(PROGRAM_ELF, stdin)
+        .run()
+        .expect("failed to execute program");
+
+    let outputs = PublicOutputs::decode(output.as_slice()).expect("failed to decode public values");
+    (outputs.status, outputs.indices())
+}
+
+/// Asserts that the host & the program agree on the given input.
+fn assert_same(samples: &[Vec<f32>], query: &[f32], k: u32, metric: Metric) {
+    let samples = EmbeddingMatrix::from_rows(samples).expect("invalid samples");
+    let (status, idxs) = execute(&samples, query, k, metric);
+    match compute_top_k(&samples, query, k as usize, metric) {
+        Ok(expected) => {
+            assert_eq!(status, STATUS_OK);
+            assert_eq!(idxs, expected, "{}", metric);
+        }
+        Err(err) => assert_eq!(status, err.code(), "{}", metric),
+    }
+}
+
+#[test]
+fn test_identical_vectors() {
+    let samples = vec![
+        vec![0.3, 0.4],
+        vec![0.1, 0.2],
+        vec![0.3, 0.4],
+        vec![0.1, 0.2],
+        vec![0.3, 0.4],
+    ];
+    for metric in Metric::ALL {
+        assert_same(&samples, &[0.3, 0.4], 3, metric);
+    }
+}
+
+#[test]
+fn test_signed_zero_distances() {
+    // inner product distances are -0.0 & +0.0 with a zero query
+    let samples = vec![vec![1.0], vec![-1.0], vec![1.0], vec![-1.0]];
+    assert_same(&samples, &[0.0], 2, Metric::InnerProduct);
+}
+
+#[test]
+fn test_nan_distances() {
+    // cosine distances overflow to NaN for large values
+    let samples = vec![vec![1e30, 1e30], vec![1e30, 1e30], vec![-1.0, 1.0]];
+    assert_same(&samples, &[1e30, 1e30], 2, Metric::Cosine);
+    assert_same(&samples[..2], &[1e30, 1e30], 2, Metric::Cosine);
+}
+
+#[test]
+fn test_nan_inputs() {
+    let samples = vec![vec![0.1, f32::NAN], vec![0.1, 0.2]];
+    assert_same(&samples, &[0.1, 0.2], 1, Metric::L2);
+    assert_same(&samples[1..], &[f32::NAN, 0.2], 1, Metric::L2);
+}